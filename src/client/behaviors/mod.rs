@@ -1,2 +1,48 @@
-pub mod trees;
-pub mod wizards;
\ No newline at end of file
+pub mod wizards;
+
+use rand::Rng;
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::generated::improbable::Vector3d;
+use rust_ldn_demo::shared::matches::MatchTracker;
+use rust_ldn_demo::shared::scheduler::Scheduler;
+use rust_ldn_demo::shared::scoreboard::Scoreboard;
+use rust_ldn_demo::shared::templates;
+use rust_ldn_demo::shared::trees::{choppable, TreeTracker};
+use rust_ldn_demo::shared::utils::get_random_coords;
+use self::wizards::WizardBehavior;
+
+const GOOD_WIZARDS: u32 = 10;
+const EVIL_WIZARDS: u32 = 5;
+const SPAWN_RADIUS: i32 = 500;
+
+/// Registers every system a wizard client runs, in dependency order.
+pub fn add_systems<C: GameConnection + 'static>(
+    scheduler: &mut Scheduler<C>,
+    config: &WorkerConfig,
+) -> Result<(), String> {
+    scheduler.add_system(TreeTracker::new(choppable))?;
+    scheduler.add_system(MatchTracker::new())?;
+    scheduler.add_system(WizardBehavior::new(config.error_policy))?;
+    scheduler.add_system(Scoreboard::new())?;
+    Ok(())
+}
+
+/// Asks for this client's wizards to be created around the origin. They are checked out to the
+/// client once they exist.
+pub fn spawn_wizards<C: GameConnection, R: Rng>(
+    connection: &mut C,
+    evil: bool,
+    rng: &mut R,
+) -> Result<(), String> {
+    let center = Vector3d { x: 0.0, y: 0.0, z: 0.0 };
+    let num_wizards = if evil { EVIL_WIZARDS } else { GOOD_WIZARDS };
+
+    for _ in 0..num_wizards {
+        let position = get_random_coords(&center, SPAWN_RADIUS, rng);
+        let wizard = templates::wizard(&position, evil, connection.get_worker_id())?;
+        connection.send_create_entity_request(wizard, None, None);
+    }
+
+    Ok(())
+}
@@ -1,55 +1,88 @@
+use rust_ldn_demo::shared::clock::SimulationTime;
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::errors::{require, BehaviourError, ErrorHandler, ErrorPolicy};
+use rust_ldn_demo::shared::matches::MatchTracker;
+use rust_ldn_demo::shared::metrics::WorkerMetrics;
+use rust_ldn_demo::shared::scheduler::{Context, Dependency, System};
+use rust_ldn_demo::shared::trees::TreeTracker;
 use rust_ldn_demo::shared::generated::demo::{WizardActionType, Wizard, WizardFaction, WizardUpdate, WizardAction, Fire, FireCommandRequest, TriggerFire};
-use spatialos_sdk::worker::connection::{WorkerConnection, Connection};
-use crate::behaviors::trees::TrackTreesBehaviour;
 use spatialos_sdk::worker::view::{View, ViewQuery};
 use rust_ldn_demo::shared::generated::improbable::{Position, Coordinates, PositionUpdate};
 use spatialos_sdk::worker::EntityId;
 use rand::seq::SliceRandom;
 use spatialos_sdk::worker::component::UpdateParameters;
-use rand::prelude::ThreadRng;
+use rand::Rng;
 use rust_ldn_demo::shared::utils::{squared_distance, normalized_direction, multiply, add_coords, move_to};
 use rust_ldn_demo::shared::generated::demo::WizardActionType::MOVING;
 use spatialos_sdk::worker::commands::CommandParameters;
 
 const SEARCH_RADIUS: f64 = 75.0;
-const MOVE_SPEED: f64 = 0.05; // At 60FPS -> 3 units/second.
+const MOVE_SPEED: f64 = 3.0; // Units per second.
 const DISTANCE_THRESHOLD: f64 = 3.0;
 
 pub struct WizardBehavior {
-    rng: ThreadRng,
     update_params: UpdateParameters,
+    errors: ErrorHandler,
 }
 
 impl WizardBehavior {
-    pub fn new() -> Self {
+    pub fn new(error_policy: ErrorPolicy) -> Self {
         let mut params = UpdateParameters::new();
         params.allow_loopback();
 
         WizardBehavior {
-            rng: rand::thread_rng(),
             update_params: params,
+            errors: ErrorHandler::new(error_policy),
         }
     }
 
-    pub fn tick(&mut self, view: &View, connection: &mut WorkerConnection, trees: &TrackTreesBehaviour) {
+    pub fn tick(&mut self, view: &View, connection: &mut impl GameConnection, trees: &TreeTracker, rng: &mut impl Rng, time: &SimulationTime) {
+        self.errors.tick(view);
 
-        for wizard in view.query::<WizardQuery>() {
-            match wizard.wiz.action.typ {
-                WizardActionType::IDLE => self.do_idle(&wizard, view, connection, trees),
-                WizardActionType::MOVING => self.do_move(&wizard, view, connection),
-                WizardActionType::SPELL => self.do_spell(&wizard, connection)
+        // Visit wizards in a stable order so that seeded runs are reproducible.
+        let mut wizards = view
+            .query::<AuthoritativeWizard>()
+            .map(|wizard| wizard.entity_id)
+            .collect::<Vec<EntityId>>();
+        wizards.sort();
+
+        for entity_id in wizards {
+            if self.errors.is_quarantined(entity_id) {
+                continue;
+            }
+
+            let result = WizardQuery::select(view, entity_id).and_then(|wizard| {
+                match wizard.wiz.action.typ {
+                    WizardActionType::IDLE => {
+                        self.do_idle(&wizard, view, connection, trees, rng);
+                        Ok(())
+                    },
+                    WizardActionType::MOVING => self.do_move(&wizard, view, connection, time),
+                    WizardActionType::SPELL => self.do_spell(&wizard, connection)
+                }
+            });
+
+            if let Err(error) = result {
+                // Wizards have nothing in flight, so quarantining one only means leaving it be.
+                if self.errors.handle(&error) == ErrorPolicy::Reset && !error.is_missing("Wizard") {
+                    self.go_idle(entity_id, connection);
+                }
             }
         }
     }
 
-    fn do_idle(&mut self, wiz: &WizardQuery, view: &View, connection: &mut WorkerConnection, trees: &TrackTreesBehaviour) {
+    fn do_idle(&mut self, wiz: &WizardQuery, view: &View, connection: &mut impl GameConnection, trees: &TreeTracker, rng: &mut impl Rng) {
         for attempts in 1..5 {
             let possible_targets = self.find_targets(wiz.wiz.faction, &wiz.position.coords, SEARCH_RADIUS * attempts as f64, trees);
-            let rand_tree = possible_targets.choose(&mut self.rng);
+            let rand_tree = possible_targets.choose(rng);
 
             match rand_tree {
                 Some(id) =>  {
-                    let target_position = view.get_component::<Position>(*id).unwrap();
+                    // The tracker can be a tick ahead of the view when a tree leaves it.
+                    let target_position = match view.get_component::<Position>(*id) {
+                        Some(position) => position,
+                        None => continue,
+                    };
 
                     connection.send_component_update::<Wizard>(
                         wiz.entity_id,
@@ -70,16 +103,17 @@ impl WizardBehavior {
         }
     }
 
-    fn do_move(&mut self, wiz: &WizardQuery, view: &View, connection: &mut WorkerConnection) {
-        let target = wiz.wiz.action.target.unwrap();
-        let target_position = wiz.wiz.action.target_pos.as_ref().unwrap();
+    fn do_move(&mut self, wiz: &WizardQuery, view: &View, connection: &mut impl GameConnection, time: &SimulationTime) -> Result<(), BehaviourError> {
+        let target = wiz.target()?;
+        let target_position = wiz.wiz.action.target_pos.as_ref()
+            .ok_or_else(|| BehaviourError::missing_target(wiz.entity_id, "Wizard"))?;
         let pos = &wiz.position.coords;
 
         if squared_distance(pos, target_position) > DISTANCE_THRESHOLD {
             connection.send_component_update::<Position>(
                 wiz.entity_id,
                 PositionUpdate {
-                    coords: Some(move_to(pos, target_position, MOVE_SPEED)),
+                    coords: Some(move_to(pos, target_position, MOVE_SPEED * time.delta)),
                 },
                 self.update_params.clone(),
             );
@@ -97,10 +131,12 @@ impl WizardBehavior {
                 self.update_params.clone(),
             );
         }
+
+        Ok(())
     }
 
-    fn do_spell(&mut self, wiz: &WizardQuery, connection: &mut WorkerConnection) {
-        let target = wiz.wiz.action.target.unwrap();
+    fn do_spell(&mut self, wiz: &WizardQuery, connection: &mut impl GameConnection) -> Result<(), BehaviourError> {
+        let target = wiz.target()?;
 
         match wiz.wiz.faction {
             WizardFaction::GOOD => {
@@ -111,8 +147,13 @@ impl WizardBehavior {
             }
         };
 
+        self.go_idle(wiz.entity_id, connection);
+        Ok(())
+    }
+
+    fn go_idle(&self, wizard_id: EntityId, connection: &mut impl GameConnection) {
         connection.send_component_update::<Wizard>(
-            wiz.entity_id,
+            wizard_id,
             WizardUpdate {
                 faction: None,
                 action: Some(WizardAction {
@@ -129,33 +170,95 @@ impl WizardBehavior {
         faction: WizardFaction,
         coords: &Coordinates,
         radius: f64,
-        trees: &TrackTreesBehaviour
+        trees: &TreeTracker
     ) -> Vec<EntityId> {
         match faction {
-            WizardFaction::GOOD => trees.within_inactive(coords.clone(), radius).collect::<Vec<EntityId>>(),
-            WizardFaction::EVIL => trees.within_active(coords.clone(), radius).collect::<Vec<EntityId>>()
+            WizardFaction::GOOD => trees.within_unavailable(coords, radius),
+            WizardFaction::EVIL => trees.within_available(coords, radius)
         }
     }
 }
 
+impl<C: GameConnection + 'static> System<C> for WizardBehavior {
+    fn name(&self) -> &'static str {
+        "wizards"
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::on::<TreeTracker>("trees"),
+            Dependency::on::<MatchTracker>("match_state"),
+        ]
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        // Wizards rest between rounds.
+        if !ctx.dependencies.get::<MatchTracker>().in_play() {
+            return;
+        }
+
+        let trees = ctx.dependencies.get::<TreeTracker>();
+        WizardBehavior::tick(self, ctx.view, ctx.connection, trees, ctx.rng, &ctx.time);
+    }
+
+    fn report(&self, view: &View, metrics: &mut WorkerMetrics) {
+        let mut counts = [
+            (WizardActionType::IDLE, "idle", 0),
+            (WizardActionType::MOVING, "moving", 0),
+            (WizardActionType::SPELL, "spell", 0),
+        ];
+
+        let wizards = view
+            .query::<AuthoritativeWizard>()
+            .filter_map(|wizard| view.get_component::<Wizard>(wizard.entity_id));
+
+        for wizard in wizards {
+            for (typ, _, count) in counts.iter_mut() {
+                if *typ == wizard.action.typ {
+                    *count += 1;
+                }
+            }
+        }
+
+        for (_, action, count) in counts.iter() {
+            metrics.set_gauge("wizards", &[("action", *action)], f64::from(*count));
+        }
+
+        self.errors.report("wizards", metrics);
+    }
+}
+
 struct WizardQuery<'a> {
     pub entity_id: EntityId,
     pub position: &'a Position,
     pub wiz: &'a Wizard
 }
 
-impl<'a, 'b: 'a> ViewQuery<'b> for WizardQuery<'a> {
+impl<'a> WizardQuery<'a> {
+    fn select(view: &'a View, entity_id: EntityId) -> Result<Self, BehaviourError> {
+        Ok(WizardQuery {
+            entity_id,
+            position: require::<Position>(view, entity_id, "Wizard", "Position")?,
+            wiz: require::<Wizard>(view, entity_id, "Wizard", "Wizard")?
+        })
+    }
+
+    fn target(&self) -> Result<EntityId, BehaviourError> {
+        self.wiz.action.target
+            .ok_or_else(|| BehaviourError::missing_target(self.entity_id, "Wizard"))
+    }
+}
+
+struct AuthoritativeWizard {
+    entity_id: EntityId
+}
+
+impl<'b> ViewQuery<'b> for AuthoritativeWizard {
     fn filter(view: &View, entity_id: EntityId) -> bool {
-        view.get_component::<Position>(entity_id).is_some() &&
-            view.get_component::<Wizard>(entity_id).is_some() &&
-            view.is_authoritative::<Wizard>(entity_id)
+        view.is_authoritative::<Wizard>(entity_id)
     }
 
-    fn select(view: &'b View, entity_id: EntityId) -> Self {
-        WizardQuery {
-            entity_id,
-            position: view.get_component::<Position>(entity_id).unwrap(),
-            wiz: view.get_component::<Wizard>(entity_id).unwrap()
-        }
+    fn select(_view: &'b View, entity_id: EntityId) -> Self {
+        AuthoritativeWizard { entity_id }
     }
 }
\ No newline at end of file
@@ -1,67 +1,61 @@
 mod behaviors;
 
+use log::{info, warn};
 use structopt::StructOpt;
 
-use rust_ldn_demo::shared::connection::get_connection;
-use rust_ldn_demo::shared::fps::{FpsTracker, FpsLimiter};
-use rust_ldn_demo::shared::opt::Opt;
-use spatialos_sdk::worker::connection::Connection;
-use spatialos_sdk::worker::op::WorkerOp;
-use spatialos_sdk::worker::view::View;
-use rust_ldn_demo::shared::templates;
-use rust_ldn_demo::shared::generated::improbable::Vector3d;
-use rust_ldn_demo::shared::utils::get_random_coords;
-use crate::behaviors::trees::TrackTreesBehaviour;
-use crate::behaviors::wizards::WizardBehavior;
+use rust_ldn_demo::shared::clock::SimulationClock;
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::connection::{connect, print_deployments, resolve_worker_id};
+use rust_ldn_demo::shared::logging;
+use rust_ldn_demo::shared::opt::{Command, Opt};
+#[cfg(feature = "prometheus")]
+use rust_ldn_demo::shared::prometheus::MetricsEndpoint;
+use rust_ldn_demo::shared::scheduler::{Scheduler, StopReason};
+use rust_ldn_demo::shared::shutdown;
+use rust_ldn_demo::shared::utils::create_rng;
 
 const WORKER_TYPE: &str = "RustClient";
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::from_args();
-    let mut connection = get_connection(WORKER_TYPE, &opt)?;
-    let mut view = View::new();
-    let mut fps_tracker = FpsTracker::new(10);
-    let mut fps_limiter = FpsLimiter::new(60.0);
 
-    let center = Vector3d { x: 0.0, y: 0.0 , z: 0.0};
-    let mut rng = rand::thread_rng();
+    if let Command::ListDeployments { ref locator } = opt.command {
+        return Ok(print_deployments(locator)?);
+    }
 
-    let num_wizards = if opt.evil { 5 } else { 10 };
+    let config = WorkerConfig::load(&opt)?;
+    logging::init(&config)?;
 
-    for i in 0..num_wizards {
-        connection.send_create_entity_request(templates::wizard(&get_random_coords(&center, 500, &mut rng), opt.evil, connection.get_worker_id())?, None, None);
-    }
+    let worker_id = resolve_worker_id(WORKER_TYPE, &opt)?;
+    info!("Starting {} as {}", WORKER_TYPE, worker_id);
 
-    let mut trees = TrackTreesBehaviour::new();
-    let mut wizards = WizardBehavior::new();
+    let mut connection = connect(WORKER_TYPE, &worker_id, &opt, &config)?;
 
-    loop {
-        view.clear_transient_data();
+    let mut rng = create_rng(opt.seed);
+    let mut clock = SimulationClock::new(60.0);
 
-        let mut in_critical_section = false;
+    behaviors::spawn_wizards(&mut connection, opt.evil, &mut rng)?;
 
-        loop {
-            let ops = connection.get_op_list(0);
-            view.process_ops(&ops);
+    shutdown::listen_for_interrupt()?;
 
-            for op in ops.iter() {
-                match op {
-                    WorkerOp::CriticalSection(_) => in_critical_section = !in_critical_section,
-                    _ => {}
-                }
-            }
+    #[cfg(feature = "prometheus")]
+    let metrics_endpoint = MetricsEndpoint::start(config.metrics_addr)?;
 
-            if !in_critical_section {
-                break;
+    loop {
+        // Start from an empty view on every connection. The wizards spawned above outlive the
+        // connection and are picked up again as they are checked out.
+        let mut scheduler = Scheduler::new(connection, config.frame_rate_cap());
+        #[cfg(feature = "prometheus")]
+        scheduler.publish_metrics_to(metrics_endpoint.clone());
+        behaviors::add_systems(&mut scheduler, &config)?;
+
+        match scheduler.run(&mut rng, &mut clock) {
+            StopReason::Disconnected(_) => {
+                warn!("Lost the connection, reconnecting");
+                connection = connect(WORKER_TYPE, &worker_id, &opt, &config)?;
             }
+            // Exit with an error so that the process supervisor restarts the worker.
+            reason => return Err(reason.into()),
         }
-
-        trees.tick(&view, &mut connection);
-        wizards.tick(&view, &mut connection, &trees);
-
-        let frame_time = fps_tracker.tick(&mut connection);
-        fps_limiter.tick(frame_time);
     }
-
-    Ok(())
 }
@@ -1,7 +1,10 @@
-use spatialos_sdk::worker::connection::{WorkerConnection, Connection};
 use spatialos_sdk::worker::view::{View, ViewQuery};
 use spatialos_sdk::worker::EntityId;
-use rust_ldn_demo::shared::generated::demo::{Headquarters, HeadquartersCommandResponse, Score, HeadquartersUpdate};
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::matches::MatchTracker;
+use rust_ldn_demo::shared::metrics::WorkerMetrics;
+use rust_ldn_demo::shared::scheduler::{Context, Dependency, System};
+use rust_ldn_demo::shared::generated::demo::{Headquarters, HeadquartersCommandRequest, HeadquartersCommandResponse, Score, HeadquartersUpdate};
 use spatialos_sdk::worker::component::UpdateParameters;
 
 pub struct HqBehaviour { }
@@ -11,24 +14,84 @@ impl HqBehaviour {
         HqBehaviour { }
     }
 
-    pub fn tick(&mut self, view: &View, connection: &mut WorkerConnection) {
+    /// Banks deposits while a round is in play and refuses them in between, so that wood from a
+    /// finished round does not count towards the next one.
+    pub fn tick(&mut self, view: &View, connection: &mut impl GameConnection, in_play: bool) {
         let mut params = UpdateParameters::new();
         params.allow_loopback();
 
         for entity in view.query::<HqScoreRequestQuery>() {
-            let requests = view.get_command_requests::<Headquarters>(entity.entity_id).unwrap();
+            let requests = match view.get_command_requests::<Headquarters>(entity.entity_id) {
+                Some(requests) => requests,
+                None => continue,
+            };
+
+            let mut deposited = 0;
+
+            for (req_id, req) in &requests {
+                match req {
+                    HeadquartersCommandRequest::Deposit(wood) => {
+                        if wood.team != entity.hq.team {
+                            connection.send_command_failure(*req_id, "Wrong team.");
+                            continue;
+                        }
+
+                        if !in_play {
+                            connection.send_command_failure(*req_id, "Round over.");
+                            continue;
+                        }
+
+                        deposited += wood.amount;
+                    }
+                }
 
-            for (req_id, _) in &requests {
                 connection.send_command_response::<Headquarters>(*req_id, HeadquartersCommandResponse::Deposit(Score {}));
             }
 
+            if deposited == 0 {
+                continue;
+            }
+
             connection.send_component_update::<Headquarters>(entity.entity_id, HeadquartersUpdate {
-                score: Some(entity.hq.score + requests.len() as u32)
+                score: Some(entity.hq.score.saturating_add(deposited)),
+                team: None,
+                lumberjack_radius: None,
             }, params);
         }
     }
 }
 
+impl<C: GameConnection + 'static> System<C> for HqBehaviour {
+    fn name(&self) -> &'static str {
+        "hqs"
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![Dependency::on::<MatchTracker>("match_state")]
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        let in_play = ctx.dependencies.get::<MatchTracker>().in_play();
+        HqBehaviour::tick(self, ctx.view, ctx.connection, in_play);
+    }
+
+    fn shutdown(&mut self, ctx: &mut Context<C>) {
+        // Bank any deposits that arrived in the last frame rather than leaving them to time out.
+        let in_play = ctx.dependencies.get::<MatchTracker>().in_play();
+        HqBehaviour::tick(self, ctx.view, ctx.connection, in_play);
+    }
+
+    fn report(&self, view: &View, metrics: &mut WorkerMetrics) {
+        // HQs can leave the view, so start from scratch rather than keep reporting stale scores.
+        metrics.clear_gauges("hq_score");
+
+        for entity in view.query::<HqQuery>() {
+            let hq = entity.entity_id.id.to_string();
+            metrics.set_gauge("hq_score", &[("hq", hq.as_str())], f64::from(entity.hq.score));
+        }
+    }
+}
+
 struct HqScoreRequestQuery<'a> {
     pub entity_id: EntityId,
     pub hq: &'a Headquarters,
@@ -43,7 +106,25 @@ impl <'a, 'b: 'a> ViewQuery<'b> for HqScoreRequestQuery<'a> {
     fn select(view: &'b View, entity_id: EntityId) -> Self {
         HqScoreRequestQuery {
             entity_id,
-            hq: view.get_component::<Headquarters>(entity_id).expect("Error")
+            hq: view.get_component::<Headquarters>(entity_id).expect("Checked by filter")
         }
     }
-}
\ No newline at end of file
+}
+
+struct HqQuery<'a> {
+    pub entity_id: EntityId,
+    pub hq: &'a Headquarters,
+}
+
+impl <'a, 'b: 'a> ViewQuery<'b> for HqQuery<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<Headquarters>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        HqQuery {
+            entity_id,
+            hq: view.get_component::<Headquarters>(entity_id).expect("Checked by filter")
+        }
+    }
+}
@@ -1,70 +1,235 @@
-use crate::behaviors::trees::TrackTreesBehaviour;
-use rust_ldn_demo::shared::generated::demo::{Action, ActionType, Chop, Headquarters, Lumberjack, LumberjackUpdate, Tree, TreeCommandRequest, TreeCommandResponse, HeadquartersCommandRequest, Score};
+use log::Level;
+use rust_ldn_demo::log_entity;
+use rust_ldn_demo::shared::clock::SimulationTime;
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::errors::{require, BehaviourError, ErrorHandler, ErrorPolicy};
+use rust_ldn_demo::shared::matches::MatchTracker;
+use rust_ldn_demo::shared::metrics::{status_label, WorkerMetrics};
+use rust_ldn_demo::shared::scheduler::{Context, Dependency, System};
+use rust_ldn_demo::shared::trees::TreeTracker;
+use super::trees::TrackTreesBehaviour;
+use rust_ldn_demo::shared::generated::demo::{Action, ActionType, Chop, Headquarters, Lumberjack, LumberjackUpdate, PendingChop, Tree, TreeCommandRequest, TreeCommandResponse, HeadquartersCommandRequest, Wood};
 use rust_ldn_demo::shared::generated::improbable::{Coordinates, Position, PositionUpdate};
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
 use spatialos_sdk::worker::view::{View, ViewQuery};
 use spatialos_sdk::worker::{EntityId, RequestId};
 
-use rand::prelude::ThreadRng;
 use rand::seq::SliceRandom;
+use rand::Rng;
 use rust_ldn_demo::shared::utils::{add_coords, multiply, normalized_direction, squared_distance, move_to};
 use spatialos_sdk::worker::commands::CommandParameters;
 use spatialos_sdk::worker::component::UpdateParameters;
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use spatialos_sdk::worker::op::StatusCode;
 
 const SEARCH_DISTANCE: f64 = 125.0;
-const MOVE_SPEED: f64 = 0.05; // At 60FPS -> 3 units/second.
+const MOVE_SPEED: f64 = 3.0; // Units per second.
 const DISTANCE_THRESHOLD: f64 = 3.0;
 
+/// Extra time to wait past a chop request's timeout before treating it as lost, in case the
+/// runtime's own timeout response is on its way.
+const RESPONSE_GRACE_SECS: f64 = 1.0;
+
+/// How lumberjacks deal with chop requests that get no answer.
+#[derive(Debug, Clone)]
+pub struct ChopPolicy {
+    pub timeout_ms: u32,
+    pub retries: u32,
+    /// Seconds before the first resend. Doubles with every failed attempt.
+    pub initial_backoff: f64,
+    pub max_backoff: f64,
+}
+
+impl ChopPolicy {
+    pub fn from_config(config: &WorkerConfig) -> Self {
+        ChopPolicy {
+            timeout_ms: config.command_timeout_ms,
+            retries: config.chop_retries,
+            initial_backoff: config.chop_retry_backoff_ms as f64 / 1000.0,
+            max_backoff: config.chop_retry_max_backoff_ms as f64 / 1000.0,
+        }
+    }
+
+    /// The delay before retry number `retry`, counting from zero.
+    fn backoff(&self, retry: u32) -> f64 {
+        (self.initial_backoff * 2f64.powi(retry as i32)).min(self.max_backoff)
+    }
+}
+
+/// A lumberjack's outstanding chop request.
+#[derive(Debug, Clone, Copy)]
+struct ChopCommand {
+    // `None` while waiting out the backoff before a resend.
+    request_id: Option<u32>,
+    attempt: u32,
+    // Simulation time at which the request counts as lost, or at which to resend it.
+    due: f64,
+}
+
 pub struct LumberjackBehavior {
-    rng: ThreadRng,
     update_params: UpdateParameters,
-    commands_in_flight: HashMap<EntityId, u32>,
+    policy: ChopPolicy,
+    carry_capacity: u32,
+    commands_in_flight: HashMap<EntityId, ChopCommand>,
+    // Lumberjacks this worker had authority over last tick.
+    authoritative: HashSet<EntityId>,
+    // Lumberjacks that wanted to bank their wood but had no HQ of their team in view. Only warned
+    // about once until they find one again.
+    stranded: HashSet<EntityId>,
+    // Chop commands that did not succeed, by status.
+    chop_failures: BTreeMap<&'static str, u64>,
+    chop_retries: u64,
+    chops_abandoned: u64,
+    errors: ErrorHandler,
 }
 
 impl LumberjackBehavior {
-    pub fn new() -> Self {
+    pub fn new(policy: ChopPolicy, carry_capacity: u32, error_policy: ErrorPolicy) -> Self {
         let mut params = UpdateParameters::new();
         params.allow_loopback();
 
         LumberjackBehavior {
-            rng: rand::thread_rng(),
             update_params: params,
+            policy,
+            carry_capacity,
             commands_in_flight: HashMap::new(),
+            authoritative: HashSet::new(),
+            stranded: HashSet::new(),
+            chop_failures: BTreeMap::new(),
+            chop_retries: 0,
+            chops_abandoned: 0,
+            errors: ErrorHandler::new(error_policy),
         }
     }
 
     pub fn tick(
         &mut self,
         view: &View,
-        connection: &mut WorkerConnection,
-        trees: &TrackTreesBehaviour,
+        connection: &mut impl GameConnection,
+        trees: &TreeTracker,
+        rng: &mut impl Rng,
+        time: &SimulationTime,
     ) {
+        self.errors.tick(view);
         let hqs = view.query::<HqQuery>().collect::<Vec<HqQuery>>();
 
-        for lumberjack in view.query::<LumberjackQuery>() {
-            match lumberjack.lumberjack.action.typ {
-                ActionType::IDLE => self.do_idle(&lumberjack, connection, trees),
-                ActionType::FETCHING => self.do_fetch(&lumberjack, view, connection),
-                ActionType::WAITING => self.do_wait(&lumberjack, view, connection, &hqs),
-                ActionType::RETURNING => self.do_return(&lumberjack, view, connection),
+        // Visit lumberjacks in a stable order so that seeded runs are reproducible.
+        let mut lumberjacks = view
+            .query::<AuthoritativeLumberjack>()
+            .map(|lumberjack| lumberjack.entity_id)
+            .collect::<Vec<EntityId>>();
+        lumberjacks.sort();
+
+        let authoritative = lumberjacks.iter().cloned().collect::<HashSet<EntityId>>();
+
+        // Requests for lumberjacks that another worker took over are that worker's business now.
+        self.commands_in_flight.retain(|id, _| authoritative.contains(id));
+        self.stranded.retain(|id| authoritative.contains(id));
+
+        for entity_id in lumberjacks {
+            if self.errors.is_quarantined(entity_id) {
+                continue;
+            }
+
+            let result = LumberjackQuery::select(view, entity_id).and_then(|lumberjack| {
+                if !self.authoritative.contains(&entity_id) {
+                    self.recover(&lumberjack, connection, time)?;
+                }
+
+                // Only a waiting lumberjack has a chop request to keep track of. Anything left over
+                // was scheduled by `do_wait` while the lumberjack's new action was on its way.
+                match lumberjack.lumberjack.action.typ {
+                    ActionType::IDLE | ActionType::RETURNING => {
+                        self.commands_in_flight.remove(&entity_id);
+                    },
+                    ActionType::FETCHING | ActionType::WAITING => {},
+                }
+
+                match lumberjack.lumberjack.action.typ {
+                    ActionType::IDLE => {
+                        self.do_idle(&lumberjack, connection, trees, &hqs, rng);
+                        Ok(())
+                    },
+                    ActionType::FETCHING => self.do_fetch(&lumberjack, view, connection, time),
+                    ActionType::WAITING => self.do_wait(&lumberjack, view, connection, trees, &hqs, time),
+                    ActionType::RETURNING => self.do_return(&lumberjack, view, connection, time),
+                }
+            });
+
+            if let Err(error) = result {
+                self.fail(&error, connection);
             }
         }
+
+        self.authoritative = authoritative;
+    }
+
+    /// Applies the error policy to a lumberjack that could not be ticked.
+    fn fail(&mut self, error: &BehaviourError, connection: &mut impl GameConnection) {
+        match self.errors.handle(error) {
+            ErrorPolicy::Skip => {},
+            ErrorPolicy::Reset => {
+                self.commands_in_flight.remove(&error.entity_id);
+
+                if !error.is_missing("Lumberjack") {
+                    self.go_idle(error.entity_id, connection);
+                }
+            },
+            ErrorPolicy::Quarantine => {
+                self.commands_in_flight.remove(&error.entity_id);
+            }
+        }
+    }
+
+    /// Picks up a lumberjack this worker just gained authority over, e.g. after a handover or a
+    /// restart. Only its component data survives, so a chop request it was waiting on is sent
+    /// again; without one there is nothing to wait for and it goes back to idle.
+    fn recover(
+        &mut self,
+        lumberjack: &LumberjackQuery,
+        connection: &mut impl GameConnection,
+        time: &SimulationTime,
+    ) -> Result<(), BehaviourError> {
+        if lumberjack.lumberjack.action.typ != ActionType::WAITING {
+            return Ok(());
+        }
+
+        let target = lumberjack.target()?;
+
+        match lumberjack.lumberjack.pending_chop {
+            Some(ref pending) => {
+                log_entity!(Level::Debug, lumberjack.entity_id, "Lumberjack", "Resending chop request after gaining authority");
+                self.send_chop(lumberjack.entity_id, target, connection, pending.attempt, time);
+            },
+            None => {
+                log_entity!(Level::Warn, lumberjack.entity_id, "Lumberjack", "Waiting without a pending chop request, going back to idle");
+                self.go_idle(lumberjack.entity_id, connection);
+            }
+        }
+
+        Ok(())
     }
 
     fn do_idle(
         &mut self,
         lumberjack: &LumberjackQuery,
-        connection: &mut WorkerConnection,
-        trees: &TrackTreesBehaviour,
+        connection: &mut impl GameConnection,
+        trees: &TreeTracker,
+        hqs: &[HqQuery],
+        rng: &mut impl Rng,
     ) {
+        // A full lumberjack that found no HQ to go to waits here for one instead of chopping more.
+        if lumberjack.lumberjack.carrying >= self.carry_capacity {
+            self.return_to_hq(lumberjack, connection, hqs, lumberjack.lumberjack.carrying);
+            return;
+        }
+
         for attempts in 1..5 {
             let possible_targets = trees
-                .within(lumberjack.position.coords.clone(), SEARCH_DISTANCE * attempts as f64)
-                .collect::<Vec<EntityId>>();
+                .within_available(&lumberjack.position.coords, SEARCH_DISTANCE * attempts as f64);
 
-            let rand_tree = possible_targets.choose(&mut self.rng);
+            let rand_tree = possible_targets.choose(rng);
 
             match rand_tree {
                 Some(id) =>  {
@@ -75,46 +240,49 @@ impl LumberjackBehavior {
                                 typ: ActionType::FETCHING,
                                 target: Some(*id),
                             }),
+                            pending_chop: None,
+                            carrying: None,
+                            team: None,
                         },
                         self.update_params.clone(),
                     );
-                    break;
+                    return;
                 },
                 None => {}
             }
         }
+
+        // No trees left nearby to fill up on, so bank what we have.
+        if lumberjack.lumberjack.carrying > 0 {
+            self.return_to_hq(lumberjack, connection, hqs, lumberjack.lumberjack.carrying);
+        }
     }
 
     fn do_fetch(
         &mut self,
         lumberjack: &LumberjackQuery,
         view: &View,
-        connection: &mut WorkerConnection,
-    ) {
-        let target = lumberjack.lumberjack.action.target.expect("Error");
+        connection: &mut impl GameConnection,
+        time: &SimulationTime,
+    ) -> Result<(), BehaviourError> {
+        let target = lumberjack.target()?;
         let pos = &lumberjack.position.coords;
 
         let target_position = match view.get_component::<Position>(target) {
             Some(pos) => pos,
-            None => return,
+            None => return Ok(()),
         };
 
         if squared_distance(pos, &target_position.coords) > DISTANCE_THRESHOLD {
             connection.send_component_update::<Position>(
                 lumberjack.entity_id,
                 PositionUpdate {
-                    coords: Some(move_to(pos, &target_position.coords, MOVE_SPEED)),
+                    coords: Some(move_to(pos, &target_position.coords, MOVE_SPEED * time.delta)),
                 },
                 self.update_params.clone(),
             );
         } else {
-            let id = connection.send_command_request::<Tree>(
-                target,
-                TreeCommandRequest::TryChop(Chop {}),
-                None,
-                CommandParameters::new(),
-            );
-            self.commands_in_flight.insert(lumberjack.entity_id, id.id);
+            self.send_chop(lumberjack.entity_id, target, connection, 0, time);
 
             connection.send_component_update::<Lumberjack>(
                 lumberjack.entity_id,
@@ -123,92 +291,271 @@ impl LumberjackBehavior {
                         typ: ActionType::WAITING,
                         target: Some(target),
                     }),
+                    pending_chop: None,
+                    carrying: None,
+                    team: None,
                 },
                 self.update_params.clone(),
             );
         }
+
+        Ok(())
     }
 
     fn do_wait(
         &mut self,
         lumberjack: &LumberjackQuery,
         view: &View,
-        connection: &mut WorkerConnection,
-        hqs: &Vec<HqQuery>,
-    ) {
-        let request_id = self.commands_in_flight.get(&lumberjack.entity_id).unwrap();
-        let target = lumberjack.lumberjack.action.target.expect("Error");
-        let pos = &lumberjack.position.coords;
+        connection: &mut impl GameConnection,
+        trees: &TreeTracker,
+        hqs: &[HqQuery],
+        time: &SimulationTime,
+    ) -> Result<(), BehaviourError> {
+        let target = lumberjack.target()?;
+
+        let command = match self.commands_in_flight.get(&lumberjack.entity_id) {
+            Some(command) => *command,
+            None => {
+                self.untracked_wait(lumberjack, connection, time);
+                return Ok(());
+            }
+        };
 
-        let response = match view.get_command_response::<Tree>(target, RequestId::new(*request_id)) {
+        let request_id = match command.request_id {
+            Some(request_id) => request_id,
+            None => {
+                if time.elapsed >= command.due {
+                    self.send_chop(lumberjack.entity_id, target, connection, command.attempt, time);
+                }
+                return Ok(());
+            }
+        };
+
+        let response = match view.get_command_response::<Tree>(target, RequestId::new(request_id)) {
             Some(r) => r,
-            None => return
+            None => {
+                if time.elapsed >= command.due {
+                    *self.chop_failures.entry("timeout").or_insert(0) += 1;
+                    self.retry_chop(lumberjack.entity_id, connection, command.attempt, time);
+                }
+                return Ok(());
+            }
         };
 
-        self.commands_in_flight.remove(&lumberjack.entity_id);
+        match response {
+            StatusCode::Success(_) => {}
+            failure => *self.chop_failures.entry(status_label(failure)).or_insert(0) += 1,
+        }
 
         match response {
             StatusCode::Success(_) => {
-                // We got them resources. Update our state.
-                let mut hq_ids = hqs
-                    .iter()
-                    .map(|hq| (hq.entity_id, squared_distance(pos, &hq.position.coords)))
-                    .collect::<Vec<(EntityId, f64)>>();
+                self.commands_in_flight.remove(&lumberjack.entity_id);
 
-                hq_ids.sort_by(|a, b| a.1.partial_cmp(&b.1).expect("Error"));
+                // Every successful chop yields one unit of wood.
+                let carrying = lumberjack.lumberjack.carrying + 1;
 
-                match hq_ids.get(0) {
-                    Some((id, _)) => connection.send_component_update::<Lumberjack>(
-                        lumberjack.entity_id,
-                        LumberjackUpdate {
-                            action: Some(Action {
-                                typ: ActionType::RETURNING,
-                                target: Some(*id),
-                            }),
-                        },
-                        self.update_params.clone(),
-                    ),
-                    None => eprintln!("No hqs found?"),
+                if carrying >= self.carry_capacity {
+                    self.return_to_hq(lumberjack, connection, hqs, carrying);
+                } else if trees.is_available(target) {
+                    self.send_chop(lumberjack.entity_id, target, connection, 0, time);
+                    self.set_carrying(lumberjack.entity_id, connection, carrying);
+                } else {
+                    // The tree ran out or caught fire, so look for another one to finish filling up on.
+                    self.go_idle(lumberjack.entity_id, connection);
+                    self.set_carrying(lumberjack.entity_id, connection, carrying);
                 }
             },
-            StatusCode::ApplicationError(_) => {
-                connection.send_component_update::<Lumberjack>(
-                    lumberjack.entity_id,
-                    LumberjackUpdate {
-                        action: Some(Action {
-                            typ: ActionType::IDLE,
-                            target: None
-                        })
-                    },
-                    self.update_params.clone(),
-                )
+            // The tree turned us down or is gone, so asking again will not help.
+            StatusCode::ApplicationError(_) | StatusCode::NotFound(_) | StatusCode::PermissionDenied(_) => {
+                self.commands_in_flight.remove(&lumberjack.entity_id);
+                self.go_idle(lumberjack.entity_id, connection);
             },
-            _ => {
-                let id = connection.send_command_request::<Tree>(
-                    target,
-                    TreeCommandRequest::TryChop(Chop {}),
-                    None,
-                    CommandParameters::new(),
-                );
-                self.commands_in_flight.insert(lumberjack.entity_id, id.id);
+            _ => self.retry_chop(lumberjack.entity_id, connection, command.attempt, time),
+        }
+
+        Ok(())
+    }
+
+    /// Handles a waiting lumberjack that this worker has no chop request on record for. Usually the
+    /// request was just answered and the lumberjack's new action has not come back to us yet, so
+    /// the request recorded on the component is only resent once that has had time to arrive.
+    /// Without one there is nothing to wait for and the lumberjack goes back to idle.
+    fn untracked_wait(
+        &mut self,
+        lumberjack: &LumberjackQuery,
+        connection: &mut impl GameConnection,
+        time: &SimulationTime,
+    ) {
+        match lumberjack.lumberjack.pending_chop {
+            Some(ref pending) => {
+                self.commands_in_flight.insert(lumberjack.entity_id, ChopCommand {
+                    request_id: None,
+                    attempt: pending.attempt,
+                    due: time.elapsed + RESPONSE_GRACE_SECS,
+                });
+            },
+            None => {
+                log_entity!(Level::Warn, lumberjack.entity_id, "Lumberjack", "Waiting without a pending chop request, going back to idle");
+                self.go_idle(lumberjack.entity_id, connection);
             }
         }
     }
 
-    fn do_return(&mut self, lumberjack: &LumberjackQuery, view: &View, connection: &mut WorkerConnection) {
-        let target = lumberjack.lumberjack.action.target.expect("Error");
+    fn send_chop(
+        &mut self,
+        lumberjack_id: EntityId,
+        target: EntityId,
+        connection: &mut impl GameConnection,
+        attempt: u32,
+        time: &SimulationTime,
+    ) {
+        let id = connection.send_command_request::<Tree>(
+            target,
+            TreeCommandRequest::TryChop(Chop {}),
+            Some(self.policy.timeout_ms),
+            CommandParameters::new(),
+        );
+
+        self.commands_in_flight.insert(lumberjack_id, ChopCommand {
+            request_id: Some(id.id),
+            attempt,
+            due: time.elapsed + f64::from(self.policy.timeout_ms) / 1000.0 + RESPONSE_GRACE_SECS,
+        });
+
+        // Written to the component so that another worker can resend it after a handover.
+        connection.send_component_update::<Lumberjack>(
+            lumberjack_id,
+            LumberjackUpdate {
+                action: None,
+                pending_chop: Some(Some(PendingChop { attempt })),
+                carrying: None,
+                team: None,
+            },
+            self.update_params.clone(),
+        );
+    }
+
+    /// Schedules a resend of a chop request that was lost, or gives up on the tree once the
+    /// retries are used up.
+    fn retry_chop(
+        &mut self,
+        lumberjack_id: EntityId,
+        connection: &mut impl GameConnection,
+        attempt: u32,
+        time: &SimulationTime,
+    ) {
+        if attempt < self.policy.retries {
+            self.chop_retries += 1;
+            self.commands_in_flight.insert(lumberjack_id, ChopCommand {
+                request_id: None,
+                attempt: attempt + 1,
+                due: time.elapsed + self.policy.backoff(attempt),
+            });
+            return;
+        }
+
+        self.chops_abandoned += 1;
+        self.commands_in_flight.remove(&lumberjack_id);
+        log_entity!(Level::Warn, lumberjack_id, "Lumberjack", "Giving up on chopping after {} attempts", attempt + 1);
+        self.go_idle(lumberjack_id, connection);
+    }
+
+    /// Sends the lumberjack back to idle. Whatever it carries it keeps, so that it can finish
+    /// filling up on another tree.
+    fn go_idle(&self, lumberjack_id: EntityId, connection: &mut impl GameConnection) {
+        connection.send_component_update::<Lumberjack>(
+            lumberjack_id,
+            LumberjackUpdate {
+                action: Some(Action {
+                    typ: ActionType::IDLE,
+                    target: None
+                }),
+                pending_chop: Some(None),
+                carrying: None,
+                team: None,
+            },
+            self.update_params.clone(),
+        )
+    }
+
+    fn set_carrying(&self, lumberjack_id: EntityId, connection: &mut impl GameConnection, carrying: u32) {
+        connection.send_component_update::<Lumberjack>(
+            lumberjack_id,
+            LumberjackUpdate {
+                action: None,
+                pending_chop: None,
+                carrying: Some(carrying),
+                team: None,
+            },
+            self.update_params.clone(),
+        )
+    }
+
+    /// Heads for the nearest HQ of the lumberjack's team with `carrying` wood. Without one in view
+    /// the lumberjack idles with its wood instead, and tries again from there.
+    fn return_to_hq(
+        &mut self,
+        lumberjack: &LumberjackQuery,
+        connection: &mut impl GameConnection,
+        hqs: &[HqQuery],
+        carrying: u32,
+    ) {
+        let pos = &lumberjack.position.coords;
+        let team = lumberjack.lumberjack.team;
+
+        let mut hq_ids = hqs
+            .iter()
+            .filter(|hq| hq.team == team)
+            .map(|hq| (hq.entity_id, squared_distance(pos, &hq.position.coords)))
+            .collect::<Vec<(EntityId, f64)>>();
+
+        hq_ids.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+
+        let (typ, target) = match hq_ids.get(0) {
+            Some((id, _)) => {
+                self.stranded.remove(&lumberjack.entity_id);
+                (ActionType::RETURNING, Some(*id))
+            },
+            None => {
+                if self.stranded.insert(lumberjack.entity_id) {
+                    log_entity!(Level::Warn, lumberjack.entity_id, "Lumberjack", "No HQs of team {} found to return to", team);
+                }
+
+                // Already idling with this load, e.g. when called from `do_idle`.
+                if lumberjack.lumberjack.action.typ == ActionType::IDLE && lumberjack.lumberjack.carrying == carrying {
+                    return;
+                }
+
+                (ActionType::IDLE, None)
+            }
+        };
+
+        connection.send_component_update::<Lumberjack>(
+            lumberjack.entity_id,
+            LumberjackUpdate {
+                action: Some(Action { typ, target }),
+                pending_chop: Some(None),
+                carrying: Some(carrying),
+                team: None,
+            },
+            self.update_params.clone(),
+        )
+    }
+
+    fn do_return(&mut self, lumberjack: &LumberjackQuery, view: &View, connection: &mut impl GameConnection, time: &SimulationTime) -> Result<(), BehaviourError> {
+        let target = lumberjack.target()?;
         let pos = &lumberjack.position.coords;
 
         let target_position = match view.get_component::<Position>(target) {
             Some(c) => c,
-            None => return,
+            None => return Ok(()),
         };
 
         if squared_distance(pos, &target_position.coords) > DISTANCE_THRESHOLD {
             connection.send_component_update::<Position>(
                 lumberjack.entity_id,
                 PositionUpdate {
-                    coords: Some(move_to(pos, &target_position.coords, MOVE_SPEED)),
+                    coords: Some(move_to(pos, &target_position.coords, MOVE_SPEED * time.delta)),
                 },
                 self.update_params.clone(),
             );
@@ -220,43 +567,131 @@ impl LumberjackBehavior {
                         typ: ActionType::IDLE,
                         target: None,
                     }),
+                    pending_chop: None,
+                    carrying: Some(0),
+                    team: None,
                 },
                 self.update_params.clone(),
             );
 
-            // Fire and forget.
-            connection.send_command_request::<Headquarters>(
-                target,
-                HeadquartersCommandRequest::Deposit(Score{}),
-                None,
-                CommandParameters::new()
-            );
+            let amount = lumberjack.lumberjack.carrying;
+            if amount > 0 {
+                // Fire and forget.
+                connection.send_command_request::<Headquarters>(
+                    target,
+                    HeadquartersCommandRequest::Deposit(Wood { amount, team: lumberjack.lumberjack.team }),
+                    None,
+                    CommandParameters::new()
+                );
+            }
         }
+
+        Ok(())
     }
 }
 
-struct LumberjackQuery<'a> {
+impl<C: GameConnection + 'static> System<C> for LumberjackBehavior {
+    fn name(&self) -> &'static str {
+        "lumberjacks"
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::on::<TrackTreesBehaviour>("trees"),
+            Dependency::on::<MatchTracker>("match_state"),
+        ]
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        // Lumberjacks down tools between rounds. Whatever they were waiting on is moot once the
+        // world is reset, and forgetting who we had authority over makes every lumberjack go
+        // through recovery when the next round starts.
+        if !ctx.dependencies.get::<MatchTracker>().in_play() {
+            self.commands_in_flight.clear();
+            self.authoritative.clear();
+            return;
+        }
+
+        let trees = ctx.dependencies.get::<TrackTreesBehaviour>().tracker();
+        LumberjackBehavior::tick(self, ctx.view, ctx.connection, trees, ctx.rng, &ctx.time);
+    }
+
+    fn report(&self, view: &View, metrics: &mut WorkerMetrics) {
+        let mut counts = [
+            (ActionType::IDLE, "idle", 0),
+            (ActionType::FETCHING, "fetching", 0),
+            (ActionType::WAITING, "waiting", 0),
+            (ActionType::RETURNING, "returning", 0),
+        ];
+
+        let lumberjacks = view
+            .query::<AuthoritativeLumberjack>()
+            .filter_map(|lumberjack| view.get_component::<Lumberjack>(lumberjack.entity_id));
+
+        for lumberjack in lumberjacks {
+            for (typ, _, count) in counts.iter_mut() {
+                if *typ == lumberjack.action.typ {
+                    *count += 1;
+                }
+            }
+        }
+
+        for (_, action, count) in counts.iter() {
+            metrics.set_gauge("lumberjacks", &[("action", *action)], f64::from(*count));
+        }
+
+        metrics.set_gauge("chop_commands_in_flight", &[], self.commands_in_flight.len() as f64);
+        metrics.set_counter("command_retries", &[("command", "chop")], self.chop_retries);
+        metrics.set_counter("commands_abandoned", &[("command", "chop")], self.chops_abandoned);
+
+        for (status, count) in &self.chop_failures {
+            metrics.set_counter("command_failures", &[("command", "chop"), ("status", *status)], *count);
+        }
+
+        self.errors.report("lumberjacks", metrics);
+    }
+}
+
+struct AuthoritativeLumberjack {
     entity_id: EntityId,
-    lumberjack: &'a Lumberjack,
-    position: &'a Position,
 }
 
-impl<'a, 'b: 'a> ViewQuery<'b> for LumberjackQuery<'a> {
+impl<'b> ViewQuery<'b> for AuthoritativeLumberjack {
     fn filter(view: &View, entity_id: EntityId) -> bool {
         view.is_authoritative::<Lumberjack>(entity_id)
     }
 
-    fn select(view: &'b View, entity_id: EntityId) -> Self {
-        LumberjackQuery {
+    fn select(_view: &'b View, entity_id: EntityId) -> Self {
+        AuthoritativeLumberjack { entity_id }
+    }
+}
+
+struct LumberjackQuery<'a> {
+    entity_id: EntityId,
+    lumberjack: &'a Lumberjack,
+    position: &'a Position,
+}
+
+impl<'a> LumberjackQuery<'a> {
+    fn select(view: &'a View, entity_id: EntityId) -> Result<Self, BehaviourError> {
+        Ok(LumberjackQuery {
             entity_id,
-            lumberjack: view.get_component::<Lumberjack>(entity_id).expect("Error"),
-            position: view.get_component::<Position>(entity_id).expect("Error"),
-        }
+            lumberjack: require::<Lumberjack>(view, entity_id, "Lumberjack", "Lumberjack")?,
+            position: require::<Position>(view, entity_id, "Lumberjack", "Position")?,
+        })
+    }
+
+    fn target(&self) -> Result<EntityId, BehaviourError> {
+        self.lumberjack
+            .action
+            .target
+            .ok_or_else(|| BehaviourError::missing_target(self.entity_id, "Lumberjack"))
     }
 }
 
 struct HqQuery<'a> {
     entity_id: EntityId,
+    team: u32,
     position: &'a Position,
 }
 
@@ -269,7 +704,167 @@ impl<'a, 'b: 'a> ViewQuery<'b> for HqQuery<'a> {
     fn select(view: &'b View, entity_id: EntityId) -> Self {
         HqQuery {
             entity_id,
-            position: view.get_component::<Position>(entity_id).expect("Error"),
+            team: view.get_component::<Headquarters>(entity_id).expect("Checked by filter").team,
+            position: view.get_component::<Position>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use rust_ldn_demo::shared::clock::SimulationClock;
+    use rust_ldn_demo::shared::generated::improbable::Vector3d;
+    use rust_ldn_demo::shared::local::{LocalConnection, LocalRuntime};
+    use rust_ldn_demo::shared::scheduler::Scheduler;
+    use rust_ldn_demo::shared::templates;
+    use rust_ldn_demo::shared::trees::choppable;
+    use rust_ldn_demo::shared::{CLIENT_LAYER, GAMELOGIC_LAYER};
+
+    const TREE: EntityId = EntityId { id: 1 };
+    const LUMBERJACK: EntityId = EntityId { id: 2 };
+    const HQ: EntityId = EntityId { id: 3 };
+
+    fn at(x: f64) -> Vector3d {
+        Vector3d { x, y: 0.0, z: 0.0 }
+    }
+
+    fn gamelogic(runtime: &LocalRuntime, config: &WorkerConfig) -> Scheduler<LocalConnection> {
+        let mut scheduler = Scheduler::new(runtime.connect("gamelogic", GAMELOGIC_LAYER), None);
+        scheduler.add_system(MatchTracker::new()).unwrap();
+        scheduler.add_system(TrackTreesBehaviour::new(config.error_policy, None)).unwrap();
+        scheduler
+            .add_system(LumberjackBehavior::new(ChopPolicy::from_config(config), config.carry_capacity, config.error_policy))
+            .unwrap();
+        scheduler
+    }
+
+    #[test]
+    fn full_lumberjacks_without_an_hq_stop_chopping() {
+        let config = WorkerConfig {
+            carry_capacity: 3,
+            ..WorkerConfig::default()
+        };
+
+        // The only HQ belongs to the other team.
+        let runtime = LocalRuntime::new();
+        runtime.add_entity(TREE, &templates::tree(&at(0.0), 100).unwrap());
+        runtime.add_entity(LUMBERJACK, &templates::lumberjack(&at(0.0), 0).unwrap());
+        runtime.add_entity(HQ, &templates::headquarters(&at(50.0), 1, 15).unwrap());
+
+        let mut scheduler = gamelogic(&runtime, &config);
+        let mut observer = runtime.connect("observer", CLIENT_LAYER);
+        let mut view = View::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut clock = SimulationClock::new(60.0);
+
+        for _ in 0..600 {
+            scheduler.tick(&mut rng, &clock.step()).unwrap();
+            observer.update_view(&mut view).unwrap();
+
+            let lumberjack = view.get_component::<Lumberjack>(LUMBERJACK).unwrap();
+            assert!(lumberjack.carrying <= config.carry_capacity, "Carrying {}", lumberjack.carrying);
         }
+
+        let lumberjack = view.get_component::<Lumberjack>(LUMBERJACK).unwrap();
+        assert_eq!(lumberjack.carrying, config.carry_capacity);
+        assert_eq!(lumberjack.action.typ, ActionType::IDLE);
+        assert_eq!(view.get_component::<Tree>(TREE).unwrap().resources_left, 100 - config.carry_capacity);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let policy = ChopPolicy {
+            timeout_ms: 500,
+            retries: 5,
+            initial_backoff: 0.25,
+            max_backoff: 0.75,
+        };
+
+        assert_eq!(policy.backoff(0), 0.25);
+        assert_eq!(policy.backoff(1), 0.5);
+        assert_eq!(policy.backoff(2), 0.75);
+        assert_eq!(policy.backoff(10), 0.75);
+    }
+
+    #[test]
+    fn unanswered_chops_are_resent_with_backoff_then_abandoned() {
+        let config = WorkerConfig {
+            command_timeout_ms: 500,
+            chop_retries: 2,
+            chop_retry_backoff_ms: 250,
+            chop_retry_max_backoff_ms: 400,
+            ..WorkerConfig::default()
+        };
+
+        let runtime = LocalRuntime::new();
+        runtime.add_entity(TREE, &templates::tree(&at(0.0), 100).unwrap());
+        runtime.add_entity(LUMBERJACK, &templates::lumberjack(&at(0.0), 0).unwrap());
+
+        // This worker is authoritative over the tree, but nothing on it answers chop requests.
+        let mut connection = runtime.connect("gamelogic", GAMELOGIC_LAYER);
+        let mut view = View::new();
+        let mut trees = TreeTracker::new(choppable);
+        let mut lumberjacks = LumberjackBehavior::new(ChopPolicy::from_config(&config), config.carry_capacity, config.error_policy);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut clock = SimulationClock::new(60.0);
+
+        let mut requests = Vec::new();
+        let mut waited = false;
+
+        let idle_at = loop {
+            let time = clock.step();
+            assert!(time.elapsed < 30.0, "The lumberjack never gave up");
+
+            view.clear_transient_data();
+            connection.update_view(&mut view).unwrap();
+
+            let received = view
+                .get_command_requests::<Tree>(TREE)
+                .map_or(0, |received| received.into_iter().count());
+            for _ in 0..received {
+                requests.push(time.elapsed);
+            }
+
+            match view.get_component::<Lumberjack>(LUMBERJACK).unwrap().action.typ {
+                ActionType::WAITING => waited = true,
+                ActionType::IDLE if waited => break time.elapsed,
+                _ => {}
+            }
+
+            trees.tick(&view);
+            lumberjacks.tick(&view, &mut connection, &trees, &mut rng, &time);
+        };
+
+        // Every wait can overrun by up to a step, and each resend waits twice: for the request to
+        // count as lost, then for the backoff.
+        let step = 1.0 / 60.0;
+        let lost = f64::from(config.command_timeout_ms) / 1000.0 + RESPONSE_GRACE_SECS;
+        let assert_gap = |from: f64, to: f64, expected: f64| {
+            let gap = to - from;
+            assert!(
+                gap >= expected - 1e-9 && gap <= expected + 2.0 * step + 1e-9,
+                "Expected a gap of {}s, got {}s",
+                expected,
+                gap
+            );
+        };
+
+        assert_eq!(requests.len(), 3, "Requests at {:?}", requests);
+        assert_gap(requests[0], requests[1], lost + 0.25);
+        assert_gap(requests[1], requests[2], lost + 0.4);
+        assert_gap(requests[2], idle_at, lost);
+
+        assert_eq!(lumberjacks.chop_retries, 2);
+        assert_eq!(lumberjacks.chops_abandoned, 1);
+        assert_eq!(lumberjacks.chop_failures.get("timeout"), Some(&3));
+        assert!(lumberjacks.commands_in_flight.is_empty());
+
+        let lumberjack = view.get_component::<Lumberjack>(LUMBERJACK).unwrap();
+        assert_eq!(lumberjack.action.typ, ActionType::IDLE);
+        assert_eq!(lumberjack.carrying, 0);
+        assert_eq!(view.get_component::<Tree>(TREE).unwrap().resources_left, 100);
     }
 }
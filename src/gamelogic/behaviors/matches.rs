@@ -0,0 +1,614 @@
+use log::{debug, info};
+use rand::Rng;
+use rust_ldn_demo::shared::clock::SimulationTime;
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::generated::demo::{Action, ActionType, Fire, FireUpdate, Headquarters, HeadquartersUpdate, Lumberjack, LumberjackUpdate, MatchPhase, MatchState, MatchStateUpdate, RoundEnd, RoundResult, TeamScore, Tree, TreeUpdate};
+use rust_ldn_demo::shared::generated::improbable::{Coordinates, MetadataUpdate, Metadata, Position, PositionUpdate, Vector3d};
+use rust_ldn_demo::shared::matches::MatchTracker;
+use rust_ldn_demo::shared::metrics::WorkerMetrics;
+use rust_ldn_demo::shared::scheduler::{Context, Dependency, System};
+use rust_ldn_demo::shared::scoreboard::Scoreboard;
+use rust_ldn_demo::shared::templates::DEFAULT_LUMBERJACK_RADIUS;
+use rust_ldn_demo::shared::utils::get_random_coords;
+use spatialos_sdk::worker::component::UpdateParameters;
+use spatialos_sdk::worker::view::{View, ViewQuery};
+use spatialos_sdk::worker::EntityId;
+use std::collections::BTreeMap;
+
+/// How often the time into the current phase is written to the match state.
+const PUBLISH_INTERVAL_SECS: f64 = 1.0;
+
+/// When rounds end and how long the world rests in between.
+#[derive(Debug, Clone)]
+pub struct MatchRules {
+    /// 0 disables the score target.
+    pub score_target: u32,
+    /// Simulated seconds. 0 disables the time limit.
+    pub time_limit_secs: u32,
+    pub end_when_trees_exhausted: bool,
+    pub intermission: f64,
+}
+
+impl MatchRules {
+    pub fn from_config(config: &WorkerConfig) -> Self {
+        MatchRules {
+            score_target: config.match_score_target,
+            time_limit_secs: config.match_time_limit_secs,
+            end_when_trees_exhausted: config.match_ends_when_trees_exhausted,
+            intermission: config.match_intermission_secs as f64,
+        }
+    }
+}
+
+/// The phase this worker is driving. Kept locally so that decisions do not depend on updates
+/// that have not come back yet.
+#[derive(Debug, Clone, Copy)]
+struct Phase {
+    round: u32,
+    phase: MatchPhase,
+    // Simulation time at which the phase started on this worker.
+    started: f64,
+}
+
+/// Ends rounds, resets the world and starts the next round. Every gamelogic worker resets the
+/// entities it has authority over, and whichever one has authority over the match state drives it.
+pub struct MatchBehaviour {
+    rules: MatchRules,
+    update_params: UpdateParameters,
+    // `None` while another worker drives the match.
+    driving: Option<Phase>,
+    last_published: f64,
+    // The last round this worker reset the world after.
+    reset_round: Option<u32>,
+    // Finished rounds, by how they ended.
+    rounds_finished: BTreeMap<&'static str, u64>,
+}
+
+impl MatchBehaviour {
+    pub fn new(rules: MatchRules) -> Self {
+        let mut params = UpdateParameters::new();
+        params.allow_loopback();
+
+        MatchBehaviour {
+            rules,
+            update_params: params,
+            driving: None,
+            last_published: 0.0,
+            reset_round: None,
+            rounds_finished: BTreeMap::new(),
+        }
+    }
+
+    pub fn tick(
+        &mut self,
+        view: &View,
+        connection: &mut impl GameConnection,
+        tracker: &MatchTracker,
+        scoreboard: &Scoreboard,
+        rng: &mut impl Rng,
+        time: &SimulationTime,
+    ) {
+        let (match_id, state) = match (tracker.entity_id(), tracker.state()) {
+            (Some(match_id), Some(state)) => (match_id, state),
+            _ => return,
+        };
+
+        if state.phase == MatchPhase::ROUND_OVER && self.reset_round != Some(state.round) {
+            self.reset_round = Some(state.round);
+            self.reset_world(view, connection, rng, state.round);
+        }
+
+        if view.is_authoritative::<MatchState>(match_id) {
+            self.drive(match_id, state, view, connection, scoreboard, time);
+        } else {
+            self.driving = None;
+        }
+    }
+
+    fn drive(
+        &mut self,
+        match_id: EntityId,
+        state: &MatchState,
+        view: &View,
+        connection: &mut impl GameConnection,
+        scoreboard: &Scoreboard,
+        time: &SimulationTime,
+    ) {
+        let current = match self.driving {
+            Some(current) => current,
+            None => {
+                // Pick up where the previous owner left off.
+                debug!("Taking over round {} ({:?}) {:.0}s in", state.round, state.phase, state.phase_elapsed);
+                self.publish_rules(match_id, state, connection);
+
+                let current = Phase {
+                    round: state.round,
+                    phase: state.phase,
+                    started: time.elapsed - state.phase_elapsed,
+                };
+                self.driving = Some(current);
+                current
+            }
+        };
+
+        let elapsed = time.elapsed - current.started;
+
+        match current.phase {
+            MatchPhase::PLAYING => {
+                if let Some(result) = self.round_result(view, scoreboard, current.round, elapsed) {
+                    info!("Round {} over: {}", current.round, describe(&result));
+                    *self.rounds_finished.entry(reason_label(result.reason)).or_insert(0) += 1;
+
+                    let next = Phase {
+                        round: current.round,
+                        phase: MatchPhase::ROUND_OVER,
+                        started: time.elapsed,
+                    };
+                    self.start_phase(match_id, connection, next, Some(result));
+                    return;
+                }
+            }
+            MatchPhase::ROUND_OVER => {
+                if elapsed >= self.rules.intermission && world_is_reset(view, scoreboard) {
+                    info!("Starting round {}", current.round + 1);
+
+                    let next = Phase {
+                        round: current.round + 1,
+                        phase: MatchPhase::PLAYING,
+                        started: time.elapsed,
+                    };
+                    self.start_phase(match_id, connection, next, None);
+                    return;
+                }
+            }
+        }
+
+        if time.elapsed - self.last_published >= PUBLISH_INTERVAL_SECS {
+            self.last_published = time.elapsed;
+
+            connection.send_component_update::<MatchState>(
+                match_id,
+                MatchStateUpdate {
+                    round: None,
+                    phase: None,
+                    phase_elapsed: Some(elapsed),
+                    last_result: None,
+                    score_target: None,
+                    time_limit_secs: None,
+                },
+                self.update_params.clone(),
+            );
+        }
+    }
+
+    /// Decides whether the round is over, and if so who won it.
+    fn round_result(&self, view: &View, scoreboard: &Scoreboard, round: u32, elapsed: f64) -> Option<RoundResult> {
+        let top_score = scoreboard.scores().values().cloned().max().unwrap_or(0);
+
+        let reason = if self.rules.score_target > 0 && top_score >= self.rules.score_target {
+            RoundEnd::SCORE_TARGET
+        } else if self.rules.time_limit_secs > 0 && elapsed >= f64::from(self.rules.time_limit_secs) {
+            RoundEnd::TIME_LIMIT
+        } else if self.rules.end_when_trees_exhausted && trees_exhausted(view) {
+            RoundEnd::TREES_EXHAUSTED
+        } else {
+            return None;
+        };
+
+        Some(RoundResult {
+            round,
+            reason,
+            winner: scoreboard.leader().map(|(team, _)| team),
+            scores: scoreboard
+                .scores()
+                .iter()
+                .map(|(team, score)| TeamScore { team: *team, score: *score })
+                .collect(),
+        })
+    }
+
+    fn start_phase(
+        &mut self,
+        match_id: EntityId,
+        connection: &mut impl GameConnection,
+        phase: Phase,
+        result: Option<RoundResult>,
+    ) {
+        connection.send_component_update::<MatchState>(
+            match_id,
+            MatchStateUpdate {
+                round: Some(phase.round),
+                phase: Some(phase.phase),
+                phase_elapsed: Some(0.0),
+                // A new round keeps showing how the last one ended.
+                last_result: result.map(Some),
+                score_target: None,
+                time_limit_secs: None,
+            },
+            self.update_params.clone(),
+        );
+
+        self.driving = Some(phase);
+        self.last_published = phase.started;
+    }
+
+    /// Writes this worker's rules to the match state, so that clients show the ones in force.
+    fn publish_rules(&self, match_id: EntityId, state: &MatchState, connection: &mut impl GameConnection) {
+        if state.score_target == self.rules.score_target && state.time_limit_secs == self.rules.time_limit_secs {
+            return;
+        }
+
+        connection.send_component_update::<MatchState>(
+            match_id,
+            MatchStateUpdate {
+                round: None,
+                phase: None,
+                phase_elapsed: None,
+                last_result: None,
+                score_target: Some(self.rules.score_target),
+                time_limit_secs: Some(self.rules.time_limit_secs),
+            },
+            self.update_params.clone(),
+        );
+    }
+
+    /// Restores trees, sends lumberjacks back to their HQ empty-handed and zeroes HQ scores, for
+    /// the entities this worker has authority over.
+    fn reset_world(&self, view: &View, connection: &mut impl GameConnection, rng: &mut impl Rng, round: u32) {
+        let mut trees = 0;
+        let mut lumberjacks = 0;
+        let mut hqs = 0;
+
+        for tree in view.query::<AuthoritativeTree>() {
+            if tree.tree.resources_left == tree.tree.max_resources && !tree.fire.is_on_fire {
+                continue;
+            }
+
+            connection.send_component_update::<Tree>(
+                tree.entity_id,
+                TreeUpdate {
+                    resources_left: Some(tree.tree.max_resources),
+                    max_resources: None,
+                },
+                self.update_params.clone(),
+            );
+
+            connection.send_component_update::<Fire>(
+                tree.entity_id,
+                FireUpdate {
+                    is_on_fire: Some(false),
+                },
+                self.update_params.clone(),
+            );
+
+            connection.send_component_update::<Metadata>(
+                tree.entity_id,
+                MetadataUpdate {
+                    entity_type: Some("Tree".into()),
+                },
+                self.update_params.clone(),
+            );
+
+            trees += 1;
+        }
+
+        // By entity id, so that a team with several HQs always sends its lumberjacks to the same one.
+        let mut bases = view
+            .query::<HqQuery>()
+            .map(|hq| (hq.entity_id, hq.hq.team, hq.hq.lumberjack_radius, hq.position.coords.clone()))
+            .collect::<Vec<(EntityId, u32, u32, Coordinates)>>();
+        bases.sort_by_key(|(entity_id, _, _, _)| *entity_id);
+
+        // Place lumberjacks in a stable order so that seeded runs are reproducible.
+        let mut authoritative = view
+            .query::<AuthoritativeLumberjack>()
+            .map(|lumberjack| (lumberjack.entity_id, lumberjack.lumberjack.team))
+            .collect::<Vec<(EntityId, u32)>>();
+        authoritative.sort();
+
+        for (entity_id, team) in authoritative {
+            connection.send_component_update::<Lumberjack>(
+                entity_id,
+                LumberjackUpdate {
+                    action: Some(Action {
+                        typ: ActionType::IDLE,
+                        target: None,
+                    }),
+                    pending_chop: Some(None),
+                    carrying: Some(0),
+                    team: None,
+                },
+                self.update_params.clone(),
+            );
+
+            let base = bases.iter().find(|(_, base_team, _, _)| *base_team == team);
+
+            if let Some((_, _, radius, coords)) = base {
+                // Snapshots made before HQs stored the radius have 0 here.
+                let radius = if *radius > 0 { *radius } else { DEFAULT_LUMBERJACK_RADIUS };
+                let center = Vector3d { x: coords.x, y: coords.y, z: coords.z };
+                let position = get_random_coords(&center, radius as i32, rng);
+
+                connection.send_component_update::<Position>(
+                    entity_id,
+                    PositionUpdate {
+                        coords: Some(Coordinates { x: position.x, y: position.y, z: position.z }),
+                    },
+                    self.update_params.clone(),
+                );
+            }
+
+            lumberjacks += 1;
+        }
+
+        for hq in view.query::<HqQuery>() {
+            if hq.hq.score == 0 || !view.is_authoritative::<Headquarters>(hq.entity_id) {
+                continue;
+            }
+
+            connection.send_component_update::<Headquarters>(
+                hq.entity_id,
+                HeadquartersUpdate {
+                    score: Some(0),
+                    team: None,
+                    lumberjack_radius: None,
+                },
+                self.update_params.clone(),
+            );
+
+            hqs += 1;
+        }
+
+        info!("Reset {} trees, {} lumberjacks and {} HQs after round {}", trees, lumberjacks, hqs, round);
+    }
+}
+
+impl<C: GameConnection + 'static> System<C> for MatchBehaviour {
+    fn name(&self) -> &'static str {
+        "match"
+    }
+
+    fn dependencies(&self) -> Vec<Dependency> {
+        vec![
+            Dependency::on::<MatchTracker>("match_state"),
+            Dependency::on::<Scoreboard>("scoreboard"),
+        ]
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        let tracker = ctx.dependencies.get::<MatchTracker>();
+        let scoreboard = ctx.dependencies.get::<Scoreboard>();
+        MatchBehaviour::tick(self, ctx.view, ctx.connection, tracker, scoreboard, ctx.rng, &ctx.time);
+    }
+
+    fn report(&self, _view: &View, metrics: &mut WorkerMetrics) {
+        for (reason, count) in &self.rounds_finished {
+            metrics.set_counter("rounds_finished", &[("reason", *reason)], *count);
+        }
+    }
+}
+
+fn reason_label(reason: RoundEnd) -> &'static str {
+    match reason {
+        RoundEnd::SCORE_TARGET => "score_target",
+        RoundEnd::TIME_LIMIT => "time_limit",
+        RoundEnd::TREES_EXHAUSTED => "trees_exhausted",
+    }
+}
+
+/// E.g. "team 1 wins on score_target (team 0 12, team 1 20)".
+fn describe(result: &RoundResult) -> String {
+    let outcome = match result.winner {
+        Some(team) => format!("team {} wins", team),
+        None => "draw".to_owned(),
+    };
+
+    let scores = result
+        .scores
+        .iter()
+        .map(|score| format!("team {} {}", score.team, score.score))
+        .collect::<Vec<String>>();
+
+    format!("{} on {} ({})", outcome, reason_label(result.reason), scores.join(", "))
+}
+
+/// Whether there are trees in the view and none of them have resources left.
+fn trees_exhausted(view: &View) -> bool {
+    let mut trees = view.query::<TreeQuery>().peekable();
+    trees.peek().is_some() && trees.all(|tree| tree.tree.resources_left == 0)
+}
+
+/// Whether the reset has come back to us, so that the next round does not start out already over.
+fn world_is_reset(view: &View, scoreboard: &Scoreboard) -> bool {
+    scoreboard.scores().values().all(|score| *score == 0) && !trees_exhausted(view)
+}
+
+struct TreeQuery<'a> {
+    tree: &'a Tree,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for TreeQuery<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<Tree>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        TreeQuery {
+            tree: view.get_component::<Tree>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+struct AuthoritativeTree<'a> {
+    entity_id: EntityId,
+    tree: &'a Tree,
+    fire: &'a Fire,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for AuthoritativeTree<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.is_authoritative::<Tree>(entity_id)
+            && view.get_component::<Tree>(entity_id).is_some()
+            && view.get_component::<Fire>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        AuthoritativeTree {
+            entity_id,
+            tree: view.get_component::<Tree>(entity_id).expect("Checked by filter"),
+            fire: view.get_component::<Fire>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+struct AuthoritativeLumberjack<'a> {
+    entity_id: EntityId,
+    lumberjack: &'a Lumberjack,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for AuthoritativeLumberjack<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.is_authoritative::<Lumberjack>(entity_id)
+            && view.get_component::<Lumberjack>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        AuthoritativeLumberjack {
+            entity_id,
+            lumberjack: view.get_component::<Lumberjack>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+struct HqQuery<'a> {
+    entity_id: EntityId,
+    hq: &'a Headquarters,
+    position: &'a Position,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for HqQuery<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<Headquarters>(entity_id).is_some()
+            && view.get_component::<Position>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        HqQuery {
+            entity_id,
+            hq: view.get_component::<Headquarters>(entity_id).expect("Checked by filter"),
+            position: view.get_component::<Position>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: MatchRules = MatchRules {
+        score_target: 500,
+        time_limit_secs: 600,
+        end_when_trees_exhausted: true,
+        intermission: 10.0,
+    };
+
+    /// A view with one HQ per `(team, score)` and one tree per entry in `trees`, holding that
+    /// many resources, and the scoreboard over it.
+    fn world(scores: &[(u32, u32)], trees: &[u32]) -> (View, Scoreboard) {
+        let mut view = View::new();
+        let mut next_id = 1;
+
+        for (team, score) in scores {
+            let entity_id = EntityId::new(next_id);
+            next_id += 1;
+
+            view.add_entity(entity_id);
+            view.add_component::<Headquarters>(entity_id, Headquarters { score: *score, team: *team, lumberjack_radius: 15 });
+            view.add_component::<Position>(entity_id, Position { coords: Coordinates { x: 0.0, y: 0.0, z: 0.0 } });
+        }
+
+        for resources in trees {
+            let entity_id = EntityId::new(next_id);
+            next_id += 1;
+
+            view.add_entity(entity_id);
+            view.add_component::<Tree>(entity_id, Tree { resources_left: *resources, max_resources: 5 });
+        }
+
+        let mut scoreboard = Scoreboard::new();
+        scoreboard.tick(&view);
+
+        (view, scoreboard)
+    }
+
+    fn result(rules: MatchRules, scores: &[(u32, u32)], trees: &[u32], elapsed: f64) -> Option<RoundResult> {
+        let (view, scoreboard) = world(scores, trees);
+        MatchBehaviour::new(rules).round_result(&view, &scoreboard, 3, elapsed)
+    }
+
+    #[test]
+    fn rounds_go_on_until_a_condition_is_met() {
+        assert!(result(RULES, &[(0, 499), (1, 20)], &[5, 0], 599.0).is_none());
+    }
+
+    #[test]
+    fn reaching_the_score_target_ends_the_round() {
+        let result = result(RULES, &[(0, 120), (1, 500)], &[5], 10.0).unwrap();
+
+        assert_eq!(result.round, 3);
+        assert_eq!(result.reason, RoundEnd::SCORE_TARGET);
+        assert_eq!(result.winner, Some(1));
+        assert_eq!(
+            result.scores.iter().map(|score| (score.team, score.score)).collect::<Vec<(u32, u32)>>(),
+            vec![(0, 120), (1, 500)]
+        );
+    }
+
+    #[test]
+    fn running_out_of_time_ends_the_round() {
+        let result = result(RULES, &[(0, 40), (1, 40)], &[5], 600.0).unwrap();
+
+        assert_eq!(result.reason, RoundEnd::TIME_LIMIT);
+        assert_eq!(result.winner, None);
+    }
+
+    #[test]
+    fn running_out_of_trees_ends_the_round() {
+        let result = result(RULES, &[(0, 40), (1, 30)], &[0, 0], 10.0).unwrap();
+
+        assert_eq!(result.reason, RoundEnd::TREES_EXHAUSTED);
+        assert_eq!(result.winner, Some(0));
+    }
+
+    #[test]
+    fn disabled_conditions_never_end_the_round() {
+        let rules = MatchRules {
+            score_target: 0,
+            time_limit_secs: 0,
+            end_when_trees_exhausted: false,
+            ..RULES
+        };
+
+        assert!(result(rules, &[(0, 100_000)], &[0], 100_000.0).is_none());
+    }
+
+    #[test]
+    fn trees_are_exhausted_once_none_has_resources_left() {
+        assert!(!trees_exhausted(&world(&[], &[]).0));
+        assert!(!trees_exhausted(&world(&[], &[0, 1, 0]).0));
+        assert!(trees_exhausted(&world(&[], &[0, 0]).0));
+    }
+
+    #[test]
+    fn the_world_is_reset_once_scores_and_trees_are_restored() {
+        let (view, scoreboard) = world(&[(0, 0), (1, 12)], &[5]);
+        assert!(!world_is_reset(&view, &scoreboard));
+
+        let (view, scoreboard) = world(&[(0, 0), (1, 0)], &[0, 0]);
+        assert!(!world_is_reset(&view, &scoreboard));
+
+        let (view, scoreboard) = world(&[(0, 0), (1, 0)], &[0, 5]);
+        assert!(world_is_reset(&view, &scoreboard));
+    }
+}
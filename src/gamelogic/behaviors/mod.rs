@@ -1,3 +1,28 @@
 pub mod trees;
 pub mod hq;
 pub mod lumberjacks;
+pub mod matches;
+
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::matches::MatchTracker;
+use rust_ldn_demo::shared::scheduler::Scheduler;
+use rust_ldn_demo::shared::scoreboard::Scoreboard;
+use self::hq::HqBehaviour;
+use self::lumberjacks::{ChopPolicy, LumberjackBehavior};
+use self::matches::{MatchBehaviour, MatchRules};
+use self::trees::TrackTreesBehaviour;
+
+/// Registers every system the gamelogic worker runs, in dependency order.
+pub fn add_systems<C: GameConnection + 'static>(
+    scheduler: &mut Scheduler<C>,
+    config: &WorkerConfig,
+) -> Result<(), String> {
+    scheduler.add_system(MatchTracker::new())?;
+    scheduler.add_system(TrackTreesBehaviour::new(config.error_policy, config.tree_regrowth()))?;
+    scheduler.add_system(LumberjackBehavior::new(ChopPolicy::from_config(config), config.carry_capacity, config.error_policy))?;
+    scheduler.add_system(HqBehaviour::new())?;
+    scheduler.add_system(Scoreboard::new())?;
+    scheduler.add_system(MatchBehaviour::new(MatchRules::from_config(config)))?;
+    Ok(())
+}
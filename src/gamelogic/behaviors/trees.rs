@@ -1,60 +1,99 @@
+use log::Level;
+use rust_ldn_demo::log_entity;
+use rust_ldn_demo::shared::clock::SimulationTime;
+use rust_ldn_demo::shared::connection::GameConnection;
+use rust_ldn_demo::shared::errors::{require, ErrorHandler, ErrorPolicy};
 use rust_ldn_demo::shared::generated::demo::{Chop, Tree, TreeCommandResponse, TreeUpdate, Fire, FireCommandRequest, FireUpdate, FireCommandResponse, TriggerFire};
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
 use spatialos_sdk::worker::view::{View, ViewQuery};
 use spatialos_sdk::worker::EntityId;
 use rust_ldn_demo::shared::generated::improbable::{Coordinates, Position, Metadata, MetadataUpdate};
-use rust_ldn_demo::shared::utils::squared_distance;
-use spatialos_sdk::worker::component::UpdateParameters;
+use rust_ldn_demo::shared::metrics::WorkerMetrics;
+use rust_ldn_demo::shared::scheduler::{Context, System};
+use rust_ldn_demo::shared::trees::{choppable, TreeTracker};
+use spatialos_sdk::worker::component::{Component, UpdateParameters};
 use std::cmp::{max, min};
-use std::collections::{HashMap, HashSet};
-use rand::prelude::ThreadRng;
+use std::collections::{BTreeMap, BTreeSet};
 use rand::Rng;
 use spatialos_sdk::worker::commands::CommandParameters;
-use std::time::{Duration, SystemTime};
 
 const FIRE_SPREAD_RADIUS: f64 = 10.0;
 const FIRE_SPREAD_CHANCE: f64 = 0.10;
-const FIRE_SPREAD_TIMEOUT_MS: u128 = 5000;
+const FIRE_SPREAD_INTERVAL_SECS: f64 = 5.0;
 
 pub struct TrackTreesBehaviour {
-    trees: HashMap<EntityId, Coordinates>,
-    inactive_trees: HashMap<EntityId, Coordinates>,
+    tracker: TreeTracker,
     params: UpdateParameters,
-    rng: ThreadRng,
-    last_spread: SystemTime
+    last_spread: f64,
+    // Seconds before a depleted tree grows back. `None` disables regrowth.
+    regrowth: Option<f64>,
+    // Simulation time at which each depleted tree we have authority over ran out, or at which we
+    // gained authority over it while empty.
+    depleted_since: BTreeMap<EntityId, f64>,
+    trees_regrown: u64,
+    chops_granted: u64,
+    chops_refused: u64,
+    errors: ErrorHandler,
 }
 
 impl TrackTreesBehaviour {
-    pub fn new() -> Self {
+    pub fn new(error_policy: ErrorPolicy, regrowth: Option<f64>) -> Self {
         let mut params = UpdateParameters::new();
         params.allow_loopback();
 
         TrackTreesBehaviour {
-            trees: HashMap::new(),
-            inactive_trees: HashMap::new(),
+            tracker: TreeTracker::new(choppable),
             params,
-            rng: rand::thread_rng(),
-            last_spread: SystemTime::now()
+            last_spread: 0.0,
+            regrowth,
+            depleted_since: BTreeMap::new(),
+            trees_regrown: 0,
+            chops_granted: 0,
+            chops_refused: 0,
+            errors: ErrorHandler::new(error_policy),
         }
     }
 
-    pub fn tick(&mut self, view: &View, connection: &mut WorkerConnection) {
-        for removed in view.iter_entities_removed() {
-            self.trees.remove(removed);
-            self.inactive_trees.remove(removed);
-        }
+    pub fn tick(&mut self, view: &View, connection: &mut impl GameConnection, rng: &mut impl Rng, time: &SimulationTime) {
+        self.tracker.tick(view);
+        self.errors.tick(view);
+        self.handle_requests(view, connection);
+        self.regrow(view, connection, time);
 
-        for ref added in view.query::<TreeAddedQuery>() {
-            if added.tree.resources_left > 0 && !added.is_on_fire {
-                self.trees.insert(added.id, added.position.coords.clone());
-                continue;
-            }
+        if time.elapsed - self.last_spread > FIRE_SPREAD_INTERVAL_SECS {
+            self.last_spread = time.elapsed;
+
+            let targets = view.query::<TreesOnFire>().flat_map(|coords| {
+                self.tracker.within_available(&coords.coords, FIRE_SPREAD_RADIUS)
+            }).collect::<BTreeSet<EntityId>>();
 
-            self.inactive_trees.insert(added.id, added.position.coords.clone());
+            for target in targets {
+                if rng.gen_bool(FIRE_SPREAD_CHANCE) {
+                    connection.send_command_request::<Fire>(target, FireCommandRequest::SetOnFire(TriggerFire {} ), None, CommandParameters::new());
+                }
+            }
         }
+    }
 
+    pub fn tracker(&self) -> &TreeTracker {
+        &self.tracker
+    }
+
+    fn handle_requests(&mut self, view: &View, connection: &mut impl GameConnection) {
         for entity in view.query::<TreeFireRequest>() {
-            let requests = view.get_command_requests::<Fire>(entity.entity_id).unwrap();
+            let requests = match view.get_command_requests::<Fire>(entity.entity_id) {
+                Some(requests) => requests,
+                None => continue,
+            };
+
+            let tree = match self.require::<Tree>(view, entity.entity_id, "Fire", "Tree") {
+                Some(tree) => tree,
+                None => {
+                    for (id, _) in requests {
+                        connection.send_command_failure(id, "Tree not available.");
+                    }
+                    continue;
+                }
+            };
 
             for (id, req)  in requests {
                 match req {
@@ -68,18 +107,16 @@ impl TrackTreesBehaviour {
                         }, self.params.clone());
 
                         connection.send_command_response::<Fire>(id, FireCommandResponse::SetOnFire(TriggerFire {}));
+                        log_entity!(Level::Debug, entity.entity_id, "Fire", "Set on fire");
 
-                        match self.trees.remove(&entity.entity_id) {
-                            Some(coords) => self.inactive_trees.insert(entity.entity_id, coords),
-                            None => continue
-                        };
+                        self.tracker.refresh(entity.entity_id, tree, &Fire { is_on_fire: true });
                     },
                     FireCommandRequest::ClearFire(_) => {
                         connection.send_component_update::<Fire>(entity.entity_id, FireUpdate {
                             is_on_fire: Some(false)
                         }, self.params.clone());
 
-                        let has_resources = view.get_component::<Tree>(entity.entity_id).unwrap().resources_left > 0;
+                        let has_resources = tree.resources_left > 0;
 
                         let entity_name = format!("Tree{}", if has_resources { "" } else { " (Empty)" });
 
@@ -88,22 +125,31 @@ impl TrackTreesBehaviour {
                         }, self.params.clone());
 
                         connection.send_command_response::<Fire>(id, FireCommandResponse::ClearFire(TriggerFire {}));
+                        log_entity!(Level::Debug, entity.entity_id, "Fire", "Fire put out");
 
-                        if has_resources {
-                            match self.inactive_trees.remove(&entity.entity_id) {
-                                Some(coords) => self.trees.insert(entity.entity_id, coords),
-                                None => continue
-                            };
-                        }
+                        self.tracker.refresh(entity.entity_id, tree, &Fire { is_on_fire: false });
                     }
                 }
             }
         }
 
         for entity in view.query::<TreeRequestQuery>() {
-            let requests = view.get_command_requests::<Tree>(entity.entity_id).unwrap();
+            let requests = match view.get_command_requests::<Tree>(entity.entity_id) {
+                Some(requests) => requests,
+                None => continue,
+            };
+
+            let fire = match self.require::<Fire>(view, entity.entity_id, "Tree", "Fire") {
+                Some(fire) => fire,
+                None => {
+                    for (id, _) in requests {
+                        connection.send_command_failure(id, "Tree not available.");
+                    }
+                    continue;
+                }
+            };
 
-            let max_responses = if self.inactive_trees.contains_key(&entity.entity_id) {
+            let max_responses = if !self.tracker.is_available(entity.entity_id) {
                 0
             } else {
                 min(requests.len(), entity.tree.resources_left as usize)
@@ -120,6 +166,9 @@ impl TrackTreesBehaviour {
                 connection.send_command_failure(requests[i].0, "Tree not available.");
             }
 
+            self.chops_granted += max_responses as u64;
+            self.chops_refused += (requests.len() - max_responses) as u64;
+
             let leftover_resources = if max_responses as u32 >= entity.tree.resources_left {
                 0
             } else {
@@ -130,70 +179,130 @@ impl TrackTreesBehaviour {
                 entity.entity_id,
                 TreeUpdate {
                     resources_left: Some(leftover_resources),
+                    max_resources: None,
                 },
                 self.params.clone(),
             );
 
-            if leftover_resources == 0 && entity.tree.resources_left > 0 {
-                self.inactive_trees.insert(entity.entity_id, self.trees.remove(&entity.entity_id).unwrap());
+            self.tracker.refresh(entity.entity_id, &Tree { resources_left: leftover_resources, max_resources: entity.tree.max_resources }, fire);
 
+            if leftover_resources == 0 && entity.tree.resources_left > 0 {
                 connection.send_component_update::<Metadata>(entity.entity_id, MetadataUpdate {
                     entity_type: Some("Tree (Empty)".into())
                 }, self.params.clone());
+                log_entity!(Level::Debug, entity.entity_id, "Tree", "Out of resources");
             }
         }
+    }
 
-        let now = SystemTime::now();
+    /// Grows resources back on trees that have been empty for the regrowth time. The trackers
+    /// make them available again once the update comes back.
+    fn regrow(&mut self, view: &View, connection: &mut impl GameConnection, time: &SimulationTime) {
+        let regrowth = match self.regrowth {
+            Some(regrowth) => regrowth,
+            None => return,
+        };
 
-        if now.duration_since(self.last_spread).unwrap().as_millis() > FIRE_SPREAD_TIMEOUT_MS {
-            self.last_spread = now;
+        // Start the clock on trees that just ran out, that were already empty when they came into
+        // view, or that we just gained authority over. Trees that grew back, left the view or were
+        // taken over by another worker stop theirs.
+        let depleted = view
+            .query::<DepletedTreeQuery>()
+            .map(|tree| tree.entity_id)
+            .collect::<BTreeSet<EntityId>>();
 
-            let targets = view.query::<TreesOnFire>().flat_map(|coords| {
-                self.within(coords.coords.clone(), FIRE_SPREAD_RADIUS)
-            }).collect::<HashSet<EntityId>>();
+        self.depleted_since.retain(|entity_id, _| depleted.contains(entity_id));
 
-            for target in targets {
-                if self.rng.gen_bool(FIRE_SPREAD_CHANCE) {
-                    connection.send_command_request::<Fire>(target, FireCommandRequest::SetOnFire(TriggerFire {} ), None, CommandParameters::new());
-                }
+        for entity_id in depleted {
+            self.depleted_since.entry(entity_id).or_insert(time.elapsed);
+        }
+
+        let due = self
+            .depleted_since
+            .iter()
+            .filter(|(_, since)| time.elapsed - **since >= regrowth)
+            .map(|(entity_id, _)| *entity_id)
+            .collect::<Vec<EntityId>>();
+
+        for entity_id in due {
+            self.depleted_since.remove(&entity_id);
+
+            let (tree, fire) = match (view.get_component::<Tree>(entity_id), view.get_component::<Fire>(entity_id)) {
+                (Some(tree), Some(fire)) => (tree, fire),
+                _ => continue,
+            };
+
+            // The tree may have been restored another way in the meantime, e.g. by a world reset.
+            // Trees from snapshots that predate `max_resources` have nothing to grow back.
+            if tree.resources_left > 0 || tree.max_resources == 0 || !view.is_authoritative::<Tree>(entity_id) {
+                continue;
+            }
+
+            connection.send_component_update::<Tree>(
+                entity_id,
+                TreeUpdate {
+                    resources_left: Some(tree.max_resources),
+                    max_resources: None,
+                },
+                self.params.clone(),
+            );
+
+            // A burning tree keeps its name until the fire is put out.
+            if !fire.is_on_fire {
+                connection.send_component_update::<Metadata>(entity_id, MetadataUpdate {
+                    entity_type: Some("Tree".into())
+                }, self.params.clone());
             }
+
+            self.tracker.refresh(entity_id, &Tree { resources_left: tree.max_resources, max_resources: tree.max_resources }, fire);
+            self.trees_regrown += 1;
+            log_entity!(Level::Debug, entity_id, "Tree", "Grew back {} resources", tree.max_resources);
         }
     }
 
-    pub fn within(
-        &self,
-        coords: Coordinates,
-        radius: f64,
-    ) -> impl Iterator<Item = EntityId> + '_ {
-        self.trees
-            .iter()
-            .filter(move |(id, c)| squared_distance(&coords, c) < radius.powi(2))
-            .map(|(id, _)| *id)
+    /// Looks up a component a tree needs to answer requests. Trees have no state to reset, so
+    /// requests to a tree without it, or to a quarantined tree, are refused.
+    fn require<'a, C: Component + 'static>(
+        &mut self,
+        view: &'a View,
+        entity_id: EntityId,
+        component: &'static str,
+        missing: &'static str,
+    ) -> Option<&'a C> {
+        if self.errors.is_quarantined(entity_id) {
+            return None;
+        }
+
+        match require::<C>(view, entity_id, component, missing) {
+            Ok(data) => Some(data),
+            Err(error) => {
+                self.errors.handle(&error);
+                None
+            }
+        }
     }
 }
 
-struct TreeAddedQuery<'a> {
-    pub id: EntityId,
-    pub position: &'a Position,
-    pub tree: &'a Tree,
-    pub is_on_fire: bool
-}
+impl<C: GameConnection + 'static> System<C> for TrackTreesBehaviour {
+    fn name(&self) -> &'static str {
+        "trees"
+    }
 
-impl<'a, 'b: 'a> ViewQuery<'b> for TreeAddedQuery<'a> {
-    fn filter(view: &View, entity_id: EntityId) -> bool {
-        view.was_entity_added(entity_id)
-            && view.get_component::<Tree>(entity_id).is_some()
-            && view.get_component::<Position>(entity_id).is_some()
-            && view.get_component::<Fire>(entity_id).is_some()
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        TrackTreesBehaviour::tick(self, ctx.view, ctx.connection, ctx.rng, &ctx.time);
     }
 
-    fn select(view: &'b View, entity_id: EntityId) -> Self {
-        TreeAddedQuery {
-            id: entity_id,
-            position: view.get_component::<Position>(entity_id).unwrap(),
-            tree: view.get_component::<Tree>(entity_id).unwrap(),
-            is_on_fire: view.get_component::<Fire>(entity_id).unwrap().is_on_fire
-        }
+    fn shutdown(&mut self, ctx: &mut Context<C>) {
+        self.tracker.tick(ctx.view);
+        self.handle_requests(ctx.view, ctx.connection);
+    }
+
+    fn report(&self, view: &View, metrics: &mut WorkerMetrics) {
+        self.tracker.report(view, metrics);
+        metrics.set_counter("tree_chops", &[("result", "granted")], self.chops_granted);
+        metrics.set_counter("tree_chops", &[("result", "refused")], self.chops_refused);
+        metrics.set_counter("trees_regrown", &[], self.trees_regrown);
+        self.errors.report("trees", metrics);
     }
 }
 
@@ -210,7 +319,28 @@ impl<'a, 'b: 'a> ViewQuery<'b> for TreeRequestQuery<'a> {
     fn select(view: &'b View, entity_id: EntityId) -> Self {
         TreeRequestQuery {
             entity_id,
-            tree: view.get_component::<Tree>(entity_id).unwrap(),
+            tree: view.get_component::<Tree>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+struct DepletedTreeQuery {
+    pub entity_id: EntityId
+}
+
+impl<'b> ViewQuery<'b> for DepletedTreeQuery {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        let depleted = match view.get_component::<Tree>(entity_id) {
+            Some(tree) => tree.resources_left == 0,
+            None => false
+        };
+
+        depleted && view.is_authoritative::<Tree>(entity_id)
+    }
+
+    fn select(_view: &'b View, entity_id: EntityId) -> Self {
+        DepletedTreeQuery {
+            entity_id
         }
     }
 }
@@ -247,7 +377,7 @@ impl<'b> ViewQuery<'b> for TreesOnFire {
 
     fn select(view: &'b View, entity_id: EntityId) -> Self {
         TreesOnFire {
-            coords: view.get_component::<Position>(entity_id).unwrap().coords.clone()
+            coords: view.get_component::<Position>(entity_id).expect("Checked by filter").coords.clone()
         }
     }
 }
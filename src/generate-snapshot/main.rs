@@ -1,84 +1,120 @@
+mod spec;
+
 use spatialos_sdk::worker::snapshot::SnapshotOutputStream;
 use std::{env, iter::from_fn, path::PathBuf};
 use structopt::StructOpt;
 
-use rand::prelude::ThreadRng;
+use crate::spec::{HeadquartersSpec, WorldSpec};
+use rand::distributions::{Distribution, Uniform};
 use rand::Rng;
 use rust_ldn_demo::shared::{generated::improbable::Vector3d, templates};
 use spatialos_sdk::worker::entity::Entity;
 use spatialos_sdk::worker::EntityId;
 use std::env::current_dir;
 use std::path::Path;
-use rust_ldn_demo::shared::utils::get_random_coords;
-
-const NUM_TREES: i32 = 2000;
-const NUM_CLUSTERS: i32 = 20;
-const TREE_CLUSTER_RADIUS: i32 = 150;
-const WORLD_RADIUS: i32 = 500;
-
-const NUM_LUMBERJACKS: i32 = 10;
-const LUMBERJACK_CLUSTER_RADIUS: i32 = 15;
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::logging;
+use rust_ldn_demo::shared::utils::{create_rng, get_random_coords};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt: Opt = Opt::from_args();
-    let mut rng = rand::thread_rng();
+
+    // Log to stderr only, so that the seed `create_rng` picks is shown for `--seed` to reproduce
+    // the world.
+    logging::init(&WorkerConfig {
+        log_to_spatialos: false,
+        ..WorkerConfig::default()
+    })?;
+
+    let mut rng = create_rng(opt.seed);
+
+    let spec = match opt.world_spec {
+        Some(ref path) => WorldSpec::load(path)?,
+        None => WorldSpec::default(),
+    };
 
     let mut target_file = env::current_dir()?;
     target_file.push(opt.snapshot_path);
 
     let mut snapshot = Snapshot::new(target_file)?;
 
-    let clusters = generate_clusters(&mut rng);
+    let clusters = generate_clusters(&spec, &mut rng);
+    let resources = Uniform::new_inclusive(spec.trees.resources.min, spec.trees.resources.max);
 
-    for cluster in &clusters {
-        for _i in 0..(NUM_TREES / NUM_CLUSTERS) {
-            snapshot.write(&templates::tree(&get_random_coords(
-                cluster,
-                TREE_CLUSTER_RADIUS,
-                &mut rng,
-            ))?)?;
+    for (cluster, count) in clusters.iter().zip(trees_per_cluster(spec.trees.count, clusters.len())) {
+        for _i in 0..count {
+            snapshot.write(&templates::tree(
+                &get_random_coords(cluster, spec.trees.cluster_radius, &mut rng),
+                resources.sample(&mut rng),
+            )?)?;
         }
     }
 
-    let hq_coord = (WORLD_RADIUS - 100) as f64;
-    generate_hq(&mut snapshot, hq_coord, &mut rng)?;
-    let hq_coord = -hq_coord;
-    generate_hq(&mut snapshot, hq_coord, &mut rng)?;
+    for (team, hq) in spec.headquarters.iter().enumerate() {
+        generate_hq(&mut snapshot, hq, team as u32, &mut rng)?;
+    }
+
+    snapshot.write(&templates::match_state()?)?;
 
     Ok(())
 }
 
-fn generate_hq(
+/// Splits `count` trees as evenly as possible, giving the first clusters one extra tree each
+/// until the remainder is used up.
+fn trees_per_cluster(count: i32, clusters: usize) -> impl Iterator<Item = i32> {
+    let clusters = clusters as i32;
+    let (share, remainder) = (count / clusters, count % clusters);
+
+    (0..clusters).map(move |i| if i < remainder { share + 1 } else { share })
+}
+
+fn generate_hq<R: Rng>(
     snapshot: &mut Snapshot,
-    coord: f64,
-    rng: &mut ThreadRng,
+    hq: &HeadquartersSpec,
+    team: u32,
+    rng: &mut R,
 ) -> Result<(), Box<dyn std::error::Error>> {
     let hq_position = Vector3d {
-        x: coord,
+        x: hq.position.x,
         y: 0.0,
-        z: coord,
+        z: hq.position.z,
     };
-    snapshot.write(&templates::headquarters(&hq_position)?)?;
+    snapshot.write(&templates::headquarters(&hq_position, team, hq.lumberjack_radius as u32)?)?;
 
-    for _i in 0..NUM_LUMBERJACKS {
+    for _i in 0..hq.lumberjacks {
         snapshot.write(&templates::lumberjack(&get_random_coords(
             &hq_position,
-            LUMBERJACK_CLUSTER_RADIUS,
+            hq.lumberjack_radius,
             rng,
-        ))?)?;
+        ), team)?)?;
     }
 
     Ok(())
 }
 
-fn generate_clusters(rng: &mut ThreadRng) -> Vec<Vector3d> {
+fn generate_clusters<R: Rng>(spec: &WorldSpec, rng: &mut R) -> Vec<Vector3d> {
+    let trees = &spec.trees;
+
+    if !trees.cluster_centers.is_empty() {
+        return trees
+            .cluster_centers
+            .iter()
+            .map(|center| Vector3d {
+                x: center.x,
+                y: 0.0,
+                z: center.z,
+            })
+            .collect();
+    }
+
     let mut count = 0;
+    let num_clusters = trees.clusters;
 
-    let max = (WORLD_RADIUS - TREE_CLUSTER_RADIUS) as f64;
+    let max = (spec.world_radius - trees.cluster_radius) as f64;
     let min = -max;
 
     from_fn(move || {
-        if count == NUM_CLUSTERS {
+        if count == num_clusters {
             return None;
         }
 
@@ -99,6 +135,12 @@ fn generate_clusters(rng: &mut ThreadRng) -> Vec<Vector3d> {
 struct Opt {
     #[structopt(short = "p", long = "snapshot-path")]
     snapshot_path: PathBuf,
+
+    #[structopt(short = "w", long = "world-spec")]
+    world_spec: Option<PathBuf>,
+
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
 }
 
 struct Snapshot {
@@ -0,0 +1,254 @@
+use rust_ldn_demo::shared::templates;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Describes the world that `generate-snapshot` produces. Every field has a default, so a spec
+/// file only needs to contain the values it changes.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldSpec {
+    /// Radius of the square area that tree clusters are placed in.
+    pub world_radius: i32,
+    pub trees: TreeSpec,
+    /// One entry per team. Teams are numbered from 0 in the order listed.
+    pub headquarters: Vec<HeadquartersSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TreeSpec {
+    /// Total number of trees, spread evenly across the clusters.
+    pub count: i32,
+    pub clusters: i32,
+    pub cluster_radius: i32,
+    /// Fixed cluster centres. When empty, `clusters` centres are placed at random.
+    pub cluster_centers: Vec<Point>,
+    /// Inclusive range that each tree's starting resources are picked from.
+    pub resources: Range,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HeadquartersSpec {
+    pub position: Point,
+    #[serde(default = "default_lumberjacks")]
+    pub lumberjacks: i32,
+    #[serde(default = "default_lumberjack_radius")]
+    pub lumberjack_radius: i32,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Point {
+    pub x: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Range {
+    pub min: u32,
+    pub max: u32,
+}
+
+impl WorldSpec {
+    /// Reads a spec from a `.json` file, or from TOML for any other extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => WorldSpec::from_json(&contents),
+            _ => WorldSpec::from_toml(&contents),
+        }
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Self, String> {
+        let spec: WorldSpec = toml::from_str(contents).map_err(|e| e.to_string())?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    pub fn from_json(contents: &str) -> Result<Self, String> {
+        let spec: WorldSpec = serde_json::from_str(contents).map_err(|e| e.to_string())?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let trees = &self.trees;
+
+        if trees.count < 0 {
+            return Err("trees.count must not be negative".to_owned());
+        }
+
+        if trees.cluster_radius <= 0 {
+            return Err("trees.cluster_radius must be positive".to_owned());
+        }
+
+        if trees.cluster_centers.is_empty() && trees.clusters <= 0 {
+            return Err("trees.clusters must be positive".to_owned());
+        }
+
+        if trees.cluster_centers.is_empty() && trees.cluster_radius >= self.world_radius {
+            return Err("trees.cluster_radius must be smaller than world_radius".to_owned());
+        }
+
+        if trees.resources.min > trees.resources.max {
+            return Err("trees.resources.min must not be larger than trees.resources.max".to_owned());
+        }
+
+        if self.headquarters.is_empty() {
+            return Err("At least one headquarters is required".to_owned());
+        }
+
+        if self.headquarters.iter().any(|hq| hq.lumberjack_radius <= 0) {
+            return Err("headquarters.lumberjack_radius must be positive".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for WorldSpec {
+    fn default() -> Self {
+        let hq_coord = 400.0;
+
+        WorldSpec {
+            world_radius: 500,
+            trees: TreeSpec::default(),
+            headquarters: vec![
+                HeadquartersSpec::at(hq_coord, hq_coord),
+                HeadquartersSpec::at(-hq_coord, -hq_coord),
+            ],
+        }
+    }
+}
+
+impl Default for TreeSpec {
+    fn default() -> Self {
+        TreeSpec {
+            count: 2000,
+            clusters: 20,
+            cluster_radius: 150,
+            cluster_centers: Vec::new(),
+            resources: Range { min: 5, max: 5 },
+        }
+    }
+}
+
+impl HeadquartersSpec {
+    fn at(x: f64, z: f64) -> Self {
+        HeadquartersSpec {
+            position: Point { x, z },
+            lumberjacks: default_lumberjacks(),
+            lumberjack_radius: default_lumberjack_radius(),
+        }
+    }
+}
+
+fn default_lumberjacks() -> i32 {
+    10
+}
+
+fn default_lumberjack_radius() -> i32 {
+    templates::DEFAULT_LUMBERJACK_RADIUS as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_spec_uses_the_defaults() {
+        let spec = WorldSpec::from_toml("").unwrap();
+
+        assert_eq!(spec.world_radius, 500);
+        assert_eq!(spec.trees.count, 2000);
+        assert_eq!(spec.headquarters.len(), 2);
+    }
+
+    #[test]
+    fn parses_toml() {
+        let spec = WorldSpec::from_toml(
+            r#"
+            world_radius = 300
+
+            [trees]
+            count = 10
+            cluster_radius = 20
+            cluster_centers = [{ x = 1.0, z = 2.0 }]
+            resources = { min = 1, max = 3 }
+
+            [[headquarters]]
+            position = { x = 5.0, z = -5.0 }
+            lumberjacks = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(spec.world_radius, 300);
+        assert_eq!(spec.trees.count, 10);
+        assert_eq!(spec.trees.clusters, 20);
+        assert_eq!(spec.trees.cluster_centers.len(), 1);
+        assert_eq!(spec.trees.resources.max, 3);
+        assert_eq!(spec.headquarters.len(), 1);
+        assert_eq!(spec.headquarters[0].lumberjacks, 3);
+        assert_eq!(spec.headquarters[0].lumberjack_radius, 15);
+    }
+
+    #[test]
+    fn parses_json() {
+        let spec = WorldSpec::from_json(
+            r#"{
+                "trees": { "count": 7, "clusters": 2 },
+                "headquarters": [{ "position": { "x": 0.0, "z": 0.0 }, "lumberjack_radius": 4 }]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(spec.world_radius, 500);
+        assert_eq!(spec.trees.count, 7);
+        assert_eq!(spec.trees.clusters, 2);
+        assert_eq!(spec.headquarters[0].lumberjacks, 10);
+        assert_eq!(spec.headquarters[0].lumberjack_radius, 4);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(WorldSpec::from_toml("world_size = 10").is_err());
+        assert!(WorldSpec::from_toml("[trees]\ncolour = \"green\"").is_err());
+        assert!(WorldSpec::from_json(r#"{ "trees": { "count": 1, "density": 2 } }"#).is_err());
+        assert!(WorldSpec::from_json(
+            r#"{ "headquarters": [{ "position": { "x": 0.0, "y": 0.0, "z": 0.0 } }] }"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_trees() {
+        let errors = [
+            "[trees]\ncount = -1",
+            "[trees]\nclusters = 0",
+            "[trees]\ncluster_radius = 0",
+            "[trees]\ncluster_radius = -5\ncluster_centers = [{ x = 0.0, z = 0.0 }]",
+            "world_radius = 100\n[trees]\ncluster_radius = 100",
+            "[trees]\nresources = { min = 3, max = 2 }",
+        ];
+
+        for contents in errors.iter() {
+            assert!(WorldSpec::from_toml(contents).is_err(), "{}", contents);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_headquarters() {
+        assert!(WorldSpec::from_json(r#"{ "headquarters": [] }"#).is_err());
+        assert!(WorldSpec::from_json(
+            r#"{ "headquarters": [{ "position": { "x": 0.0, "z": 0.0 }, "lumberjack_radius": 0 }] }"#
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use crate::shared::utils::duration_secs;
+use std::time::Instant;
+
+/// The longest stretch of real time a single frame may advance the simulation by, so that a
+/// stalled worker does not teleport everything once it recovers.
+const MAX_FRAME_SECONDS: f64 = 0.25;
+
+/// Snapshot of the simulation clock for a single frame. All times are in seconds.
+#[derive(Debug, Clone, Copy)]
+pub struct SimulationTime {
+    /// Simulated time since the previous frame. Always a whole number of fixed steps.
+    pub delta: f64,
+    /// Simulated time since the clock was created.
+    pub elapsed: f64,
+    /// Number of frames since the clock was created.
+    pub tick: u64,
+}
+
+/// Advances simulated time in fixed steps, independently of how fast the worker is running.
+pub struct SimulationClock {
+    step: f64,
+    accumulator: f64,
+    last: Instant,
+    time: SimulationTime,
+}
+
+impl SimulationClock {
+    pub fn new(steps_per_second: f64) -> Self {
+        SimulationClock {
+            step: 1.0 / steps_per_second,
+            accumulator: 0.0,
+            last: Instant::now(),
+            time: SimulationTime {
+                delta: 0.0,
+                elapsed: 0.0,
+                tick: 0,
+            },
+        }
+    }
+
+    /// Advances the clock by as many fixed steps as fit into the real time since the last call.
+    /// Any remainder carries over to the next frame.
+    pub fn advance(&mut self) -> SimulationTime {
+        let now = Instant::now();
+        let real_delta = now.duration_since(self.last);
+        self.last = now;
+
+        self.accumulator += duration_secs(real_delta).min(MAX_FRAME_SECONDS);
+
+        let steps = (self.accumulator / self.step).floor();
+        self.accumulator -= steps * self.step;
+
+        self.advance_by(steps * self.step)
+    }
+
+    /// Advances the clock by exactly one fixed step, ignoring real time. Used where runs must be
+    /// reproducible regardless of how long each frame took.
+    pub fn step(&mut self) -> SimulationTime {
+        let step = self.step;
+        self.advance_by(step)
+    }
+
+    fn advance_by(&mut self, delta: f64) -> SimulationTime {
+        self.time.delta = delta;
+        self.time.elapsed += delta;
+        self.time.tick += 1;
+
+        self.time
+    }
+}
@@ -0,0 +1,523 @@
+use crate::shared::errors::ErrorPolicy;
+use crate::shared::logging::LogFilter;
+use crate::shared::opt::{Command, Opt};
+use serde::Deserialize;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Prefix of every environment variable that overrides a setting, e.g. `RUST_LDN_TARGET_FPS`.
+const ENV_PREFIX: &str = "RUST_LDN_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    Tcp,
+    Raknet,
+}
+
+impl FromStr for Transport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "tcp" => Ok(Transport::Tcp),
+            "raknet" => Ok(Transport::Raknet),
+            _ => Err(format!("Unknown transport \"{}\", expected tcp or raknet", s)),
+        }
+    }
+}
+
+/// How a worker connects and runs. Built from, in increasing order of precedence, the defaults
+/// below, a TOML or JSON config file, `RUST_LDN_*` environment variables and command line flags.
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// `None` uses TCP for the receptionist and RakNet for the locator.
+    pub transport: Option<Transport>,
+    /// `None` only uses the external IP when connecting through the locator.
+    pub use_external_ip: Option<bool>,
+    pub internal_serialization: bool,
+    pub connection_timeout_ms: u64,
+    /// Only applies to RakNet.
+    pub heartbeat_timeout_ms: u32,
+    pub command_timeout_ms: u32,
+    /// How many times a lumberjack resends a chop request that timed out or failed in transit
+    /// before going back to idle.
+    pub chop_retries: u32,
+    /// Delay before the first resend. Doubles with every failed attempt.
+    pub chop_retry_backoff_ms: u64,
+    pub chop_retry_max_backoff_ms: u64,
+    /// How much wood a lumberjack chops before taking it back to an HQ.
+    pub carry_capacity: u32,
+    /// How long a tree that ran out of resources takes to grow them back. 0 disables regrowth.
+    pub tree_regrowth_secs: u32,
+    /// A round ends once a team's score reaches this. 0 disables it.
+    pub match_score_target: u32,
+    /// A round ends after this many simulated seconds. 0 disables it.
+    pub match_time_limit_secs: u32,
+    /// A round ends once no tree in the view has resources left.
+    pub match_ends_when_trees_exhausted: bool,
+    /// How long to wait between rounds while the world is reset.
+    pub match_intermission_secs: u64,
+    /// What behaviours do with an entity they cannot act on, e.g. a lumberjack without a target.
+    pub error_policy: ErrorPolicy,
+    pub log_level: LogFilter,
+    /// Also append log records to this file.
+    pub log_file: Option<PathBuf>,
+    pub log_to_stderr: bool,
+    /// Forward log records to the SpatialOS worker log.
+    pub log_to_spatialos: bool,
+    pub target_fps: f64,
+    /// Run frames back to back, ignoring `target_fps`, e.g. for benchmarks.
+    pub uncapped: bool,
+    /// Serve metrics for Prometheus on this address. Needs the `prometheus` feature.
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            transport: None,
+            use_external_ip: None,
+            internal_serialization: true,
+            connection_timeout_ms: 60_000,
+            heartbeat_timeout_ms: 60_000,
+            command_timeout_ms: 5_000,
+            chop_retries: 3,
+            chop_retry_backoff_ms: 250,
+            chop_retry_max_backoff_ms: 2_000,
+            carry_capacity: 5,
+            tree_regrowth_secs: 120,
+            match_score_target: 500,
+            match_time_limit_secs: 600,
+            match_ends_when_trees_exhausted: true,
+            match_intermission_secs: 10,
+            error_policy: ErrorPolicy::Reset,
+            log_level: "info".parse().expect("The default log level is valid"),
+            log_file: None,
+            log_to_stderr: true,
+            log_to_spatialos: true,
+            target_fps: 60.0,
+            uncapped: false,
+            metrics_addr: None,
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// Layers the config file named by `--config` or `RUST_LDN_CONFIG`, the environment and the
+    /// command line over the defaults.
+    pub fn load(options: &Opt) -> Result<Self, String> {
+        WorkerConfig::layered(options.config.as_ref().map(PathBuf::as_path), ConfigLayer::from_opt(options))
+    }
+
+    /// Layers the given config file, or the one named by `RUST_LDN_CONFIG`, and the environment
+    /// over the defaults. For tools which do not take the workers' command line.
+    pub fn load_without_flags(path: Option<&Path>) -> Result<Self, String> {
+        WorkerConfig::layered(path, ConfigLayer::default())
+    }
+
+    fn layered(path: Option<&Path>, flags: ConfigLayer) -> Result<Self, String> {
+        let mut config = WorkerConfig::default();
+
+        let path = match path {
+            Some(path) => Some(path.to_owned()),
+            None => env_var::<PathBuf>("CONFIG")?,
+        };
+
+        if let Some(path) = path {
+            config.apply(ConfigLayer::from_file(&path)?)?;
+        }
+
+        config.apply(ConfigLayer::from_env()?)?;
+        config.apply(flags)?;
+
+        if !(config.target_fps > 0.0) {
+            return Err(format!("target_fps must be positive, not {}", config.target_fps));
+        }
+
+        if config.carry_capacity == 0 {
+            return Err("carry_capacity must be at least 1".to_owned());
+        }
+
+        if config.metrics_addr.is_some() && !cfg!(feature = "prometheus") {
+            return Err(
+                "metrics_addr is set, but this worker was built without the prometheus feature"
+                    .to_owned(),
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// The frame rate to pace the main loop to, or `None` when uncapped.
+    pub fn frame_rate_cap(&self) -> Option<f64> {
+        if self.uncapped {
+            None
+        } else {
+            Some(self.target_fps)
+        }
+    }
+
+    /// Seconds before a depleted tree grows its resources back, or `None` when trees never do.
+    pub fn tree_regrowth(&self) -> Option<f64> {
+        if self.tree_regrowth_secs == 0 {
+            None
+        } else {
+            Some(f64::from(self.tree_regrowth_secs))
+        }
+    }
+
+    pub fn transport_for(&self, command: &Command) -> Transport {
+        match (self.transport, command) {
+            (Some(transport), _) => transport,
+            (None, Command::Receptionist { .. }) => Transport::Tcp,
+            (None, _) => Transport::Raknet,
+        }
+    }
+
+    pub fn use_external_ip_for(&self, command: &Command) -> bool {
+        match (self.use_external_ip, command) {
+            (Some(use_external_ip), _) => use_external_ip,
+            (None, Command::Receptionist { .. }) => false,
+            (None, _) => true,
+        }
+    }
+
+    fn apply(&mut self, layer: ConfigLayer) -> Result<(), String> {
+        if layer.transport.is_some() {
+            self.transport = layer.transport;
+        }
+
+        if layer.use_external_ip.is_some() {
+            self.use_external_ip = layer.use_external_ip;
+        }
+
+        if let Some(internal_serialization) = layer.internal_serialization {
+            self.internal_serialization = internal_serialization;
+        }
+
+        if let Some(timeout) = layer.connection_timeout_ms {
+            self.connection_timeout_ms = timeout;
+        }
+
+        if let Some(timeout) = layer.heartbeat_timeout_ms {
+            self.heartbeat_timeout_ms = timeout;
+        }
+
+        if let Some(timeout) = layer.command_timeout_ms {
+            self.command_timeout_ms = timeout;
+        }
+
+        if let Some(retries) = layer.chop_retries {
+            self.chop_retries = retries;
+        }
+
+        if let Some(backoff) = layer.chop_retry_backoff_ms {
+            self.chop_retry_backoff_ms = backoff;
+        }
+
+        if let Some(backoff) = layer.chop_retry_max_backoff_ms {
+            self.chop_retry_max_backoff_ms = backoff;
+        }
+
+        if let Some(capacity) = layer.carry_capacity {
+            self.carry_capacity = capacity;
+        }
+
+        if let Some(regrowth) = layer.tree_regrowth_secs {
+            self.tree_regrowth_secs = regrowth;
+        }
+
+        if let Some(target) = layer.match_score_target {
+            self.match_score_target = target;
+        }
+
+        if let Some(limit) = layer.match_time_limit_secs {
+            self.match_time_limit_secs = limit;
+        }
+
+        if let Some(ends) = layer.match_ends_when_trees_exhausted {
+            self.match_ends_when_trees_exhausted = ends;
+        }
+
+        if let Some(intermission) = layer.match_intermission_secs {
+            self.match_intermission_secs = intermission;
+        }
+
+        if let Some(error_policy) = layer.error_policy {
+            self.error_policy = error_policy;
+        }
+
+        if let Some(ref level) = layer.log_level {
+            self.log_level = level.parse()?;
+        }
+
+        if layer.log_file.is_some() {
+            self.log_file = layer.log_file;
+        }
+
+        if let Some(log_to_stderr) = layer.log_to_stderr {
+            self.log_to_stderr = log_to_stderr;
+        }
+
+        if let Some(log_to_spatialos) = layer.log_to_spatialos {
+            self.log_to_spatialos = log_to_spatialos;
+        }
+
+        if let Some(target_fps) = layer.target_fps {
+            self.target_fps = target_fps;
+        }
+
+        if let Some(uncapped) = layer.uncapped {
+            self.uncapped = uncapped;
+        }
+
+        if layer.metrics_addr.is_some() {
+            self.metrics_addr = layer.metrics_addr;
+        }
+
+        Ok(())
+    }
+}
+
+/// One source of settings. Anything left out keeps the value from the layers below.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigLayer {
+    transport: Option<Transport>,
+    use_external_ip: Option<bool>,
+    internal_serialization: Option<bool>,
+    connection_timeout_ms: Option<u64>,
+    heartbeat_timeout_ms: Option<u32>,
+    command_timeout_ms: Option<u32>,
+    chop_retries: Option<u32>,
+    chop_retry_backoff_ms: Option<u64>,
+    chop_retry_max_backoff_ms: Option<u64>,
+    carry_capacity: Option<u32>,
+    tree_regrowth_secs: Option<u32>,
+    match_score_target: Option<u32>,
+    match_time_limit_secs: Option<u32>,
+    match_ends_when_trees_exhausted: Option<bool>,
+    match_intermission_secs: Option<u64>,
+    error_policy: Option<ErrorPolicy>,
+    log_level: Option<String>,
+    log_file: Option<PathBuf>,
+    log_to_stderr: Option<bool>,
+    log_to_spatialos: Option<bool>,
+    target_fps: Option<f64>,
+    uncapped: Option<bool>,
+    metrics_addr: Option<SocketAddr>,
+}
+
+impl ConfigLayer {
+    /// Reads a `.json` file, or TOML for any other extension.
+    fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+
+        let layer = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::from_str(&contents).map_err(|e| e.to_string()),
+            _ => toml::from_str(&contents).map_err(|e| e.to_string()),
+        };
+
+        layer.map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+
+    fn from_env() -> Result<Self, String> {
+        Ok(ConfigLayer {
+            transport: env_var("TRANSPORT")?,
+            use_external_ip: env_var("USE_EXTERNAL_IP")?,
+            internal_serialization: env_var("INTERNAL_SERIALIZATION")?,
+            connection_timeout_ms: env_var("CONNECTION_TIMEOUT_MS")?,
+            heartbeat_timeout_ms: env_var("HEARTBEAT_TIMEOUT_MS")?,
+            command_timeout_ms: env_var("COMMAND_TIMEOUT_MS")?,
+            chop_retries: env_var("CHOP_RETRIES")?,
+            chop_retry_backoff_ms: env_var("CHOP_RETRY_BACKOFF_MS")?,
+            chop_retry_max_backoff_ms: env_var("CHOP_RETRY_MAX_BACKOFF_MS")?,
+            carry_capacity: env_var("CARRY_CAPACITY")?,
+            tree_regrowth_secs: env_var("TREE_REGROWTH_SECS")?,
+            match_score_target: env_var("MATCH_SCORE_TARGET")?,
+            match_time_limit_secs: env_var("MATCH_TIME_LIMIT_SECS")?,
+            match_ends_when_trees_exhausted: env_var("MATCH_ENDS_WHEN_TREES_EXHAUSTED")?,
+            match_intermission_secs: env_var("MATCH_INTERMISSION_SECS")?,
+            error_policy: env_var("ERROR_POLICY")?,
+            log_level: env_var("LOG_LEVEL")?,
+            log_file: env_var("LOG_FILE")?,
+            log_to_stderr: env_var("LOG_TO_STDERR")?,
+            log_to_spatialos: env_var("LOG_TO_SPATIALOS")?,
+            target_fps: env_var("TARGET_FPS")?,
+            uncapped: env_var("UNCAPPED")?,
+            metrics_addr: env_var("METRICS_ADDR")?,
+        })
+    }
+
+    fn from_opt(options: &Opt) -> Self {
+        let use_external_ip = match options.command {
+            Command::Receptionist {
+                connect_with_external_ip: true,
+                ..
+            } => Some(true),
+            _ => options.use_external_ip,
+        };
+
+        ConfigLayer {
+            transport: options.transport,
+            use_external_ip,
+            internal_serialization: options.internal_serialization,
+            connection_timeout_ms: options.connection_timeout_ms,
+            heartbeat_timeout_ms: options.heartbeat_timeout_ms,
+            command_timeout_ms: options.command_timeout_ms,
+            chop_retries: options.chop_retries,
+            chop_retry_backoff_ms: options.chop_retry_backoff_ms,
+            chop_retry_max_backoff_ms: options.chop_retry_max_backoff_ms,
+            carry_capacity: options.carry_capacity,
+            tree_regrowth_secs: options.tree_regrowth_secs,
+            match_score_target: options.match_score_target,
+            match_time_limit_secs: options.match_time_limit_secs,
+            match_ends_when_trees_exhausted: options.match_ends_when_trees_exhausted,
+            match_intermission_secs: options.match_intermission_secs,
+            error_policy: options.error_policy,
+            log_level: options.log_level.clone(),
+            log_file: options.log_file.clone(),
+            log_to_stderr: options.log_to_stderr,
+            log_to_spatialos: options.log_to_spatialos,
+            target_fps: options.target_fps,
+            uncapped: if options.uncapped { Some(true) } else { None },
+            metrics_addr: options.metrics_addr,
+        }
+    }
+}
+
+fn env_var<T>(name: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let key = format!("{}{}", ENV_PREFIX, name);
+
+    match env::var(&key) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| format!("Invalid {}={:?}: {}", key, value, e)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(e) => Err(format!("Invalid {}: {}", key, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+
+    /// Writes `contents` to a file named after the test, so that tests can run in parallel.
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("rust-ldn-{}-{}", ::std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn opt(flags: &[&str]) -> Opt {
+        let mut args = vec!["worker"];
+        args.extend_from_slice(flags);
+        args.push("receptionist");
+        Opt::from_iter(args)
+    }
+
+    #[test]
+    fn empty_layers_keep_the_defaults() {
+        let mut config = WorkerConfig::default();
+        config.apply(ConfigLayer::default()).unwrap();
+
+        assert_eq!(config.carry_capacity, 5);
+        assert_eq!(config.match_score_target, 500);
+        assert!(config.log_to_spatialos);
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let mut config = WorkerConfig::default();
+
+        config
+            .apply(ConfigLayer {
+                carry_capacity: Some(7),
+                chop_retries: Some(1),
+                ..ConfigLayer::default()
+            })
+            .unwrap();
+
+        config
+            .apply(ConfigLayer {
+                chop_retries: Some(9),
+                ..ConfigLayer::default()
+            })
+            .unwrap();
+
+        assert_eq!(config.carry_capacity, 7);
+        assert_eq!(config.chop_retries, 9);
+        assert_eq!(config.command_timeout_ms, 5_000);
+    }
+
+    #[test]
+    fn reads_toml_and_json_files() {
+        let toml = config_file("read.toml", "carry_capacity = 2\nerror_policy = \"skip\"\n");
+        let json = config_file("read.json", r#"{ "carry_capacity": 3, "log_to_stderr": false }"#);
+
+        let toml = ConfigLayer::from_file(&toml).unwrap();
+        let json = ConfigLayer::from_file(&json).unwrap();
+
+        assert_eq!(toml.carry_capacity, Some(2));
+        assert_eq!(toml.error_policy, Some(ErrorPolicy::Skip));
+        assert_eq!(json.carry_capacity, Some(3));
+        assert_eq!(json.log_to_stderr, Some(false));
+        assert_eq!(json.error_policy, None);
+    }
+
+    #[test]
+    fn rejects_unknown_settings_in_files() {
+        let path = config_file("unknown.toml", "carry_capacty = 2\n");
+        let error = ConfigLayer::from_file(&path).unwrap_err();
+
+        assert!(error.contains("carry_capacty"), "{}", error);
+    }
+
+    #[test]
+    fn invalid_values_in_files_are_errors() {
+        let path = config_file("invalid.toml", "log_level = \"loud\"\n");
+        let mut config = WorkerConfig::default();
+
+        assert!(config.apply(ConfigLayer::from_file(&path).unwrap()).is_err());
+    }
+
+    // The only test that touches the environment, since tests share it.
+    #[test]
+    fn flags_win_over_the_environment_which_wins_over_the_file() {
+        let path = config_file(
+            "layers.toml",
+            "carry_capacity = 2\nmatch_score_target = 100\nmatch_time_limit_secs = 60\n",
+        );
+        let path = path.to_str().unwrap();
+
+        env::set_var("RUST_LDN_MATCH_SCORE_TARGET", "200");
+        env::set_var("RUST_LDN_MATCH_TIME_LIMIT_SECS", "120");
+        let config = WorkerConfig::load(&opt(&["--config", path, "--match-time-limit-secs", "180"]));
+        env::remove_var("RUST_LDN_MATCH_SCORE_TARGET");
+        env::remove_var("RUST_LDN_MATCH_TIME_LIMIT_SECS");
+
+        let config = config.unwrap();
+        assert_eq!(config.chop_retries, 3);
+        assert_eq!(config.carry_capacity, 2);
+        assert_eq!(config.match_score_target, 200);
+        assert_eq!(config.match_time_limit_secs, 180);
+
+        env::set_var("RUST_LDN_CARRY_CAPACITY", "lots");
+        let error = WorkerConfig::load(&opt(&[])).unwrap_err();
+        env::remove_var("RUST_LDN_CARRY_CAPACITY");
+
+        assert!(error.contains("RUST_LDN_CARRY_CAPACITY"), "{}", error);
+    }
+}
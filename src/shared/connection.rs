@@ -1,29 +1,343 @@
-use crate::shared::opt::{Command, Opt};
+use crate::shared::config::{Transport, WorkerConfig};
+use crate::shared::opt::{Command, LocatorOpt, Opt};
 use futures::future::Future;
-use spatialos_sdk::worker::connection::WorkerConnection;
+use log::{info, warn};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use spatialos_sdk::worker::commands::{
+    CommandParameters, CreateEntityRequest, IncomingCommandRequest, OutgoingCommandRequest,
+};
+use spatialos_sdk::worker::component::{Component, UpdateParameters};
+use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
 use spatialos_sdk::worker::constants::LOCATOR_HOSTNAME;
 use spatialos_sdk::worker::constants::RECEPTIONIST_PORT;
-use spatialos_sdk::worker::locator::Locator;
+use spatialos_sdk::worker::entity::Entity;
+use spatialos_sdk::worker::locator::{Deployment, Locator};
 use spatialos_sdk::worker::locator::{LocatorCredentials, LocatorParameters};
-use spatialos_sdk::worker::parameters::ConnectionParameters;
+use spatialos_sdk::worker::metrics::Metrics;
+use spatialos_sdk::worker::op::WorkerOp;
+use spatialos_sdk::worker::parameters::{ConnectionParameters, RakNetNetworkParameters};
+use spatialos_sdk::worker::view::View;
+use spatialos_sdk::worker::{EntityId, LogLevel, RequestId};
+use std::fmt;
+use std::io::{self, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub fn get_connection(worker_type: &str, options: &Opt) -> Result<WorkerConnection, String> {
-    let worker_id = match options.worker_id {
-        Some(ref id) => id.clone(),
-        None => format!("{}-{}", worker_type, "TODO"),
-    };
+const MAX_WORKER_ID_LENGTH: usize = 128;
+const WORKER_ID_SUFFIX_LENGTH: usize = 6;
 
-    let connection_future = match &options.command {
-        Command::Receptionist {
-            host,
-            port,
-            connect_with_external_ip,
-        } => {
-            let params = ConnectionParameters::new(worker_type)
-                .using_tcp()
-                .using_external_ip(*connect_with_external_ip)
-                .enable_internal_serialization();
+/// The subset of a SpatialOS connection that the behaviours rely on. Implemented by the real
+/// `WorkerConnection` and by `LocalConnection`, which runs against an in-process world.
+pub trait GameConnection {
+    fn get_worker_id(&self) -> &str;
+
+    fn get_worker_attributes(&self) -> Vec<String>;
+
+    /// Applies all ops received since the last call to the view. Never stops part way through a
+    /// critical section.
+    ///
+    /// Returns the reason given by the runtime if the worker has been disconnected.
+    fn update_view(&mut self, view: &mut View) -> Result<(), String>;
+
+    fn send_component_update<C: Component + 'static>(
+        &mut self,
+        entity_id: EntityId,
+        update: C::Update,
+        params: UpdateParameters,
+    ) where
+        C::Update: Clone;
+
+    fn send_command_request<C: Component + 'static>(
+        &mut self,
+        entity_id: EntityId,
+        request: C::CommandRequest,
+        timeout_millis: Option<u32>,
+        params: CommandParameters,
+    ) -> RequestId<OutgoingCommandRequest>;
+
+    fn send_command_response<C: Component + 'static>(
+        &mut self,
+        request_id: RequestId<IncomingCommandRequest>,
+        response: C::CommandResponse,
+    );
+
+    fn send_command_failure(&mut self, request_id: RequestId<IncomingCommandRequest>, message: &str);
+
+    fn send_create_entity_request(
+        &mut self,
+        entity: Entity,
+        entity_id: Option<EntityId>,
+        timeout_millis: Option<u32>,
+    ) -> RequestId<CreateEntityRequest>;
+
+    fn send_metrics(&mut self, metrics: &Metrics);
+
+    fn send_log_message(
+        &mut self,
+        level: LogLevel,
+        logger_name: &str,
+        message: &str,
+        entity_id: Option<EntityId>,
+    );
+}
+
+impl GameConnection for WorkerConnection {
+    fn get_worker_id(&self) -> &str {
+        Connection::get_worker_id(self)
+    }
+
+    fn get_worker_attributes(&self) -> Vec<String> {
+        Connection::get_worker_attributes(self).to_vec()
+    }
+
+    fn update_view(&mut self, view: &mut View) -> Result<(), String> {
+        let mut in_critical_section = false;
+
+        loop {
+            let ops = self.get_op_list(0);
+            view.process_ops(&ops);
+
+            for op in ops.iter() {
+                match op {
+                    WorkerOp::CriticalSection(_) => in_critical_section = !in_critical_section,
+                    WorkerOp::Disconnect(op) => return Err(op.reason.clone()),
+                    _ => {}
+                }
+            }
+
+            if !in_critical_section {
+                return Ok(());
+            }
+        }
+    }
+
+    fn send_component_update<C: Component + 'static>(
+        &mut self,
+        entity_id: EntityId,
+        update: C::Update,
+        params: UpdateParameters,
+    ) where
+        C::Update: Clone,
+    {
+        Connection::send_component_update::<C>(self, entity_id, update, params)
+    }
+
+    fn send_command_request<C: Component + 'static>(
+        &mut self,
+        entity_id: EntityId,
+        request: C::CommandRequest,
+        timeout_millis: Option<u32>,
+        params: CommandParameters,
+    ) -> RequestId<OutgoingCommandRequest> {
+        Connection::send_command_request::<C>(self, entity_id, request, timeout_millis, params)
+    }
+
+    fn send_command_response<C: Component + 'static>(
+        &mut self,
+        request_id: RequestId<IncomingCommandRequest>,
+        response: C::CommandResponse,
+    ) {
+        Connection::send_command_response::<C>(self, request_id, response)
+    }
+
+    fn send_command_failure(&mut self, request_id: RequestId<IncomingCommandRequest>, message: &str) {
+        Connection::send_command_failure(self, request_id, message)
+    }
+
+    fn send_create_entity_request(
+        &mut self,
+        entity: Entity,
+        entity_id: Option<EntityId>,
+        timeout_millis: Option<u32>,
+    ) -> RequestId<CreateEntityRequest> {
+        Connection::send_create_entity_request(self, entity, entity_id, timeout_millis)
+    }
+
+    fn send_metrics(&mut self, metrics: &Metrics) {
+        Connection::send_metrics(self, metrics)
+    }
+
+    fn send_log_message(
+        &mut self,
+        level: LogLevel,
+        logger_name: &str,
+        message: &str,
+        entity_id: Option<EntityId>,
+    ) {
+        Connection::send_log_message(self, level, logger_name, message, entity_id)
+    }
+}
+
+/// The worker id to connect with: the one passed with `--worker-id` if it is valid, otherwise a
+/// freshly generated one. Resolve it once at startup so that reconnecting keeps the same id.
+pub fn resolve_worker_id(worker_type: &str, options: &Opt) -> Result<String, String> {
+    match options.worker_id {
+        Some(ref id) => validate_worker_id(id).map(|_| id.clone()),
+        None => Ok(generate_worker_id(worker_type)),
+    }
+}
+
+/// Worker ids end up in `workerId:<id>` attributes and entity ACLs, so they are restricted to
+/// characters that cannot be confused with the attribute syntax.
+pub fn validate_worker_id(id: &str) -> Result<(), String> {
+    if id.is_empty() {
+        return Err("The worker id must not be empty".to_owned());
+    }
+
+    if id.len() > MAX_WORKER_ID_LENGTH {
+        return Err(format!(
+            "The worker id \"{}\" is longer than {} characters",
+            id, MAX_WORKER_ID_LENGTH
+        ));
+    }
+
+    match id.chars().find(|c| !is_worker_id_char(*c)) {
+        Some(c) => Err(format!(
+            "The worker id \"{}\" contains '{}'; only letters, digits, '-', '_' and '.' are allowed",
+            id, c
+        )),
+        None => Ok(()),
+    }
+}
 
+/// `<worker type>-<hostname>-<pid>-<random suffix>`, so that workers started without an id on
+/// the same or different machines never collide.
+pub fn generate_worker_id(worker_type: &str) -> String {
+    let hostname = hostname::get_hostname()
+        .map(|name| name.chars().filter(|c| is_worker_id_char(*c)).collect::<String>())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "unknown".to_owned());
+
+    let suffix = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(WORKER_ID_SUFFIX_LENGTH)
+        .collect::<String>()
+        .to_lowercase();
+
+    format!("{}-{}-{}-{}", worker_type, hostname, process::id(), suffix)
+}
+
+fn is_worker_id_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+}
+
+/// How often and for how long to keep trying to connect.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    pub retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    pub jitter: f64,
+    pub max_duration: Duration,
+}
+
+impl ReconnectPolicy {
+    pub fn from_opt(options: &Opt) -> Self {
+        ReconnectPolicy {
+            retries: options.reconnect_retries,
+            initial_backoff: Duration::from_millis(options.reconnect_backoff_ms),
+            max_backoff: Duration::from_millis(options.reconnect_max_backoff_ms),
+            jitter: options.reconnect_jitter.max(0.0).min(1.0),
+            max_duration: Duration::from_secs(options.reconnect_max_duration_secs),
+        }
+    }
+
+    /// The delay before retry number `retry`, counting from zero.
+    fn backoff(&self, retry: u32, rng: &mut impl Rng) -> Duration {
+        let exponential = self.initial_backoff.as_millis() as f64 * 2f64.powi(retry as i32);
+        let capped = exponential.min(self.max_backoff.as_millis() as f64);
+
+        let jitter = if self.jitter > 0.0 {
+            rng.gen_range(-self.jitter, self.jitter)
+        } else {
+            0.0
+        };
+
+        Duration::from_millis((capped * (1.0 + jitter)).round() as u64)
+    }
+}
+
+/// Why a connection attempt failed.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// Worth retrying, e.g. the locator or the runtime could not be reached.
+    Transient(String),
+    /// Retrying will not help, e.g. no deployment matches the requested name or tag.
+    Fatal(String),
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConnectError::Transient(reason) | ConnectError::Fatal(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Connects like `get_connection`, retrying with exponential backoff according to the policy in
+/// `options`. Also used to get back into a deployment after the connection drops.
+pub fn connect(
+    worker_type: &str,
+    worker_id: &str,
+    options: &Opt,
+    config: &WorkerConfig,
+) -> Result<WorkerConnection, String> {
+    let policy = ReconnectPolicy::from_opt(options);
+    let mut rng = rand::thread_rng();
+    let start = Instant::now();
+    let mut retry = 0;
+
+    loop {
+        let error = match get_connection(worker_type, worker_id, options, config) {
+            Ok(connection) => {
+                info!(
+                    "Connected as {} with attributes {:?}",
+                    connection.get_worker_id(),
+                    connection.get_worker_attributes()
+                );
+                return Ok(connection);
+            }
+            Err(ConnectError::Fatal(error)) => return Err(error),
+            Err(ConnectError::Transient(error)) => error,
+        };
+
+        if retry >= policy.retries {
+            return Err(format!(
+                "Could not connect after {} attempts: {}",
+                retry + 1,
+                error
+            ));
+        }
+
+        let backoff = policy.backoff(retry, &mut rng);
+        if start.elapsed() + backoff > policy.max_duration {
+            return Err(format!(
+                "Could not connect within {:?}: {}",
+                policy.max_duration, error
+            ));
+        }
+
+        retry += 1;
+        warn!(
+            "Connection attempt {} failed: {}. Retrying in {:?}",
+            retry, error, backoff
+        );
+        thread::sleep(backoff);
+    }
+}
+
+pub fn get_connection(
+    worker_type: &str,
+    worker_id: &str,
+    options: &Opt,
+    config: &WorkerConfig,
+) -> Result<WorkerConnection, ConnectError> {
+    let params = connection_parameters(worker_type, &options.command, config);
+
+    let connection_future = match &options.command {
+        Command::Receptionist { host, port, .. } => {
             let host = match host {
                 Some(ref h) => h.clone(),
                 None => "127.0.0.1".to_owned(),
@@ -31,42 +345,356 @@ pub fn get_connection(worker_type: &str, options: &Opt) -> Result<WorkerConnecti
 
             let port = port.unwrap_or(RECEPTIONIST_PORT);
 
-            WorkerConnection::connect_receptionist_async(&worker_id, &host, port, &params)
+            WorkerConnection::connect_receptionist_async(worker_id, &host, port, &params)
         }
         Command::Locator {
-            token,
-            project_name,
+            locator,
+            deployment_name,
+            deployment_tag,
         } => {
-            let params = ConnectionParameters::new(worker_type)
-                .using_raknet()
-                .using_external_ip(true)
-                .enable_internal_serialization();
-
-            let locator_params =
-                LocatorParameters::new(project_name, LocatorCredentials::login_token(token));
-            let locator = Locator::new(LOCATOR_HOSTNAME, &locator_params);
-
-            let deployment_list_future = locator.get_deployment_list_async();
-            let deployment_list = deployment_list_future.wait()?;
-
-            if deployment_list.is_empty() {
-                return Err("No deployments could be found".to_owned())?;
-            }
-
-            let deployment = &deployment_list[0].deployment_name;
+            let locator = get_locator(locator);
+            let deployment_name = choose_deployment(
+                &locator,
+                deployment_name.as_ref().map(String::as_str),
+                deployment_tag.as_ref().map(String::as_str),
+            )?;
+            info!("Connecting to deployment {}", deployment_name);
 
             WorkerConnection::connect_locator_async(
                 &locator,
-                deployment,
+                &deployment_name,
                 &params,
                 queue_status_callback,
             )
         }
+        Command::ListDeployments { .. } => {
+            return Err(ConnectError::Fatal(
+                "list-deployments does not connect to a deployment".to_owned(),
+            ));
+        }
     };
 
-    connection_future.wait()
+    connection_future.wait().map_err(ConnectError::Transient)
+}
+
+fn connection_parameters(
+    worker_type: &str,
+    command: &Command,
+    config: &WorkerConfig,
+) -> ConnectionParameters {
+    let params = ConnectionParameters::new(worker_type);
+
+    let mut params = match config.transport_for(command) {
+        Transport::Tcp => params.using_tcp(),
+        Transport::Raknet => params.using_raknet_with_params(RakNetNetworkParameters {
+            heartbeat_timeout_millis: config.heartbeat_timeout_ms,
+        }),
+    }
+    .using_external_ip(config.use_external_ip_for(command));
+
+    if config.internal_serialization {
+        params = params.enable_internal_serialization();
+    }
+
+    params.network.connection_timeout_millis = config.connection_timeout_ms;
+    params.network.default_command_timeout_millis = config.command_timeout_ms;
+
+    params
+}
+
+/// Where the deployments to connect to come from. The locator in workers, a fake in tests.
+pub trait DeploymentSource {
+    fn deployments(&self) -> Result<Vec<Deployment>, String>;
+}
+
+impl DeploymentSource for Locator {
+    fn deployments(&self) -> Result<Vec<Deployment>, String> {
+        self.get_deployment_list_async().wait()
+    }
+}
+
+/// The name of the deployment to connect to. Failing to get the list is worth retrying, but a
+/// list without a matching deployment is not.
+fn choose_deployment(
+    source: &impl DeploymentSource,
+    name: Option<&str>,
+    tag: Option<&str>,
+) -> Result<String, ConnectError> {
+    let deployments = source.deployments().map_err(ConnectError::Transient)?;
+
+    select_deployment(&deployments, name, tag)
+        .map(|deployment| deployment.deployment_name.clone())
+        .map_err(ConnectError::Fatal)
+}
+
+/// Picks the first deployment that matches both the name and the tag, where given.
+pub fn select_deployment<'a>(
+    deployments: &'a [Deployment],
+    name: Option<&str>,
+    tag: Option<&str>,
+) -> Result<&'a Deployment, String> {
+    if deployments.is_empty() {
+        return Err("The locator did not return any deployments".to_owned());
+    }
+
+    let found = deployments.iter().find(|deployment| {
+        name.map_or(true, |name| deployment.deployment_name == name)
+            && tag.map_or(true, |tag| has_tag(deployment, tag))
+    });
+
+    match found {
+        Some(deployment) => Ok(deployment),
+        None => {
+            let mut criteria = Vec::new();
+            if let Some(name) = name {
+                criteria.push(format!("name \"{}\"", name));
+            }
+            if let Some(tag) = tag {
+                criteria.push(format!("tag \"{}\"", tag));
+            }
+
+            let available = deployments
+                .iter()
+                .map(|deployment| deployment.deployment_name.as_str())
+                .collect::<Vec<&str>>();
+
+            Err(format!(
+                "No deployment matches {}. Available deployments: {}",
+                criteria.join(" and "),
+                available.join(", ")
+            ))
+        }
+    }
+}
+
+/// Prints every deployment the locator offers, for the `list-deployments` subcommand.
+pub fn print_deployments(options: &LocatorOpt) -> Result<(), String> {
+    write_deployments(&get_locator(options), &mut io::stdout())
+}
+
+fn write_deployments(source: &impl DeploymentSource, out: &mut impl Write) -> Result<(), String> {
+    let deployments = source.deployments()?;
+
+    if deployments.is_empty() {
+        writeln!(out, "The locator did not return any deployments").map_err(|e| e.to_string())?;
+        return Ok(());
+    }
+
+    writeln!(
+        out,
+        "{:<32} {:<24} {:>9}  {}",
+        "NAME", "ASSEMBLY", "USERS", "DESCRIPTION"
+    )
+    .map_err(|e| e.to_string())?;
+
+    for deployment in &deployments {
+        writeln!(
+            out,
+            "{:<32} {:<24} {:>9}  {}",
+            deployment.deployment_name,
+            deployment.assembly_name,
+            format!("{}/{}", deployment.users_connected, deployment.users_capacity),
+            deployment.description
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn get_locator(options: &LocatorOpt) -> Locator {
+    let locator_params = LocatorParameters::new(
+        &options.project_name,
+        LocatorCredentials::login_token(&options.token),
+    );
+
+    Locator::new(LOCATOR_HOSTNAME, &locator_params)
+}
+
+/// The locator does not report deployment tags, so they are read from the description as
+/// `tag:<tag>` markers, separated by whitespace or commas. Other words in the description never
+/// count as tags, so "not live" does not match `live`.
+fn has_tag(deployment: &Deployment, tag: &str) -> bool {
+    let marker = format!("tag:{}", tag);
+
+    deployment
+        .description
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .any(|word| word == marker)
 }
 
-fn queue_status_callback(_queue_status: &Result<u32, String>) -> bool {
+fn queue_status_callback(queue_status: &Result<u32, String>) -> bool {
+    match queue_status {
+        Ok(position) => info!("Waiting in the locator queue at position {}", position),
+        Err(e) => warn!("Locator queue error: {}", e),
+    }
+
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deployment(name: &str, description: &str) -> Deployment {
+        Deployment {
+            deployment_name: name.to_owned(),
+            assembly_name: "assembly".to_owned(),
+            description: description.to_owned(),
+            users_connected: 0,
+            users_capacity: 10,
+        }
+    }
+
+    fn deployments() -> Vec<Deployment> {
+        vec![
+            deployment("alpha", "tag:staging, not live"),
+            deployment("beta", "tag:dev  tag:live"),
+            deployment("gamma", "tag:dev,tag:nightly"),
+        ]
+    }
+
+    enum FakeLocator {
+        Unreachable,
+        Offering(Vec<(&'static str, &'static str)>),
+    }
+
+    impl DeploymentSource for FakeLocator {
+        fn deployments(&self) -> Result<Vec<Deployment>, String> {
+            match self {
+                FakeLocator::Unreachable => Err("Could not reach the locator".to_owned()),
+                FakeLocator::Offering(deployments) => Ok(deployments
+                    .iter()
+                    .map(|(name, description)| deployment(name, description))
+                    .collect()),
+            }
+        }
+    }
+
+    fn printed(source: &FakeLocator) -> Result<String, String> {
+        let mut out = Vec::new();
+        write_deployments(source, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn selects_the_first_deployment_without_criteria() {
+        let deployments = deployments();
+        let selected = select_deployment(&deployments, None, None).unwrap();
+        assert_eq!(selected.deployment_name, "alpha");
+    }
+
+    #[test]
+    fn selects_by_name() {
+        let deployments = deployments();
+        let selected = select_deployment(&deployments, Some("gamma"), None).unwrap();
+        assert_eq!(selected.deployment_name, "gamma");
+    }
+
+    #[test]
+    fn selects_by_whitespace_separated_tag() {
+        let deployments = deployments();
+        let selected = select_deployment(&deployments, None, Some("live")).unwrap();
+        assert_eq!(selected.deployment_name, "beta");
+    }
+
+    #[test]
+    fn selects_by_comma_separated_tag() {
+        let deployments = deployments();
+        let selected = select_deployment(&deployments, None, Some("nightly")).unwrap();
+        assert_eq!(selected.deployment_name, "gamma");
+    }
+
+    #[test]
+    fn ignores_words_that_are_not_tag_markers() {
+        let deployments = deployments();
+        let selected = select_deployment(&deployments, None, Some("live")).unwrap();
+        assert_eq!(selected.deployment_name, "beta");
+
+        let error = select_deployment(&deployments, None, Some("not")).unwrap_err();
+        assert!(error.contains("tag \"not\""), "{}", error);
+    }
+
+    #[test]
+    fn selects_by_name_and_tag() {
+        let deployments = deployments();
+        let selected = select_deployment(&deployments, Some("gamma"), Some("dev")).unwrap();
+        assert_eq!(selected.deployment_name, "gamma");
+    }
+
+    #[test]
+    fn lists_available_deployments_when_nothing_matches() {
+        let deployments = deployments();
+        let error = select_deployment(&deployments, Some("alpha"), Some("dev")).unwrap_err();
+
+        assert!(error.contains("name \"alpha\" and tag \"dev\""), "{}", error);
+        assert!(error.ends_with("Available deployments: alpha, beta, gamma"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_an_empty_deployment_list() {
+        let error = select_deployment(&[], None, None).unwrap_err();
+        assert_eq!(error, "The locator did not return any deployments");
+    }
+
+    #[test]
+    fn chooses_a_deployment_from_the_locator() {
+        let locator = FakeLocator::Offering(vec![("alpha", "tag:staging"), ("beta", "tag:live")]);
+
+        let chosen = choose_deployment(&locator, None, Some("live")).unwrap();
+        assert_eq!(chosen, "beta");
+    }
+
+    #[test]
+    fn retries_when_the_locator_is_unreachable() {
+        match choose_deployment(&FakeLocator::Unreachable, None, None) {
+            Err(ConnectError::Transient(error)) => assert_eq!(error, "Could not reach the locator"),
+            other => panic!("Expected a transient error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn gives_up_when_no_deployment_matches() {
+        let locator = FakeLocator::Offering(vec![("alpha", "tag:staging")]);
+
+        match choose_deployment(&locator, Some("beta"), None) {
+            Err(ConnectError::Fatal(error)) => assert!(error.contains("name \"beta\""), "{}", error),
+            other => panic!("Expected a fatal error, got {:?}", other),
+        }
+
+        match choose_deployment(&FakeLocator::Offering(vec![]), None, None) {
+            Err(ConnectError::Fatal(error)) => {
+                assert_eq!(error, "The locator did not return any deployments")
+            }
+            other => panic!("Expected a fatal error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn prints_one_row_per_deployment() {
+        let locator = FakeLocator::Offering(vec![("alpha", "tag:staging"), ("beta", "tag:live")]);
+        let printed = printed(&locator).unwrap();
+        let lines = printed.lines().collect::<Vec<&str>>();
+
+        assert_eq!(lines.len(), 3, "{}", printed);
+        assert!(lines[0].starts_with("NAME"), "{}", printed);
+        assert!(lines[0].ends_with("USERS  DESCRIPTION"), "{}", printed);
+        assert!(lines[1].starts_with("alpha "), "{}", printed);
+        assert!(lines[1].contains(" assembly "), "{}", printed);
+        assert!(lines[1].ends_with(" 0/10  tag:staging"), "{}", printed);
+        assert!(lines[2].starts_with("beta "), "{}", printed);
+        assert!(lines[2].ends_with(" 0/10  tag:live"), "{}", printed);
+    }
+
+    #[test]
+    fn prints_when_there_are_no_deployments() {
+        let printed = printed(&FakeLocator::Offering(vec![])).unwrap();
+        assert_eq!(printed, "The locator did not return any deployments\n");
+    }
+
+    #[test]
+    fn fails_to_print_when_the_locator_is_unreachable() {
+        let error = printed(&FakeLocator::Unreachable).unwrap_err();
+        assert_eq!(error, "Could not reach the locator");
+    }
+}
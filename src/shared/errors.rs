@@ -0,0 +1,203 @@
+use crate::log_entity;
+use crate::shared::metrics::WorkerMetrics;
+use log::Level;
+use serde::Deserialize;
+use spatialos_sdk::worker::component::Component;
+use spatialos_sdk::worker::view::View;
+use spatialos_sdk::worker::EntityId;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Why a behaviour could not act on an entity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BehaviourError {
+    pub entity_id: EntityId,
+    /// The component the behaviour was working on, e.g. "Lumberjack".
+    pub component: &'static str,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ErrorKind {
+    /// The entity lacks a component that the behaviour needs, named here.
+    MissingComponent(&'static str),
+    /// The entity's current action needs a target, but it has none.
+    MissingTarget,
+}
+
+impl BehaviourError {
+    pub fn missing_component(
+        entity_id: EntityId,
+        component: &'static str,
+        missing: &'static str,
+    ) -> Self {
+        BehaviourError {
+            entity_id,
+            component,
+            kind: ErrorKind::MissingComponent(missing),
+        }
+    }
+
+    pub fn missing_target(entity_id: EntityId, component: &'static str) -> Self {
+        BehaviourError {
+            entity_id,
+            component,
+            kind: ErrorKind::MissingTarget,
+        }
+    }
+
+    /// Whether the entity lacks the `missing` component. Resetting an entity is pointless when it
+    /// lacks the component that holds the state being reset.
+    pub fn is_missing(&self, missing: &str) -> bool {
+        self.kind == ErrorKind::MissingComponent(missing)
+    }
+
+    /// A short label for metrics, e.g. `missing_target`.
+    pub fn label(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::MissingComponent(_) => "missing_component",
+            ErrorKind::MissingTarget => "missing_target",
+        }
+    }
+}
+
+impl fmt::Display for BehaviourError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ErrorKind::MissingComponent(missing) => {
+                write!(f, "{} has no {} component", self.component, missing)
+            }
+            ErrorKind::MissingTarget => write!(f, "{} action has no target", self.component),
+        }
+    }
+}
+
+impl Error for BehaviourError {}
+
+/// Looks up a component that `entity_id` needs to have for the behaviour to act on it.
+pub fn require<'a, C: Component + 'static>(
+    view: &'a View,
+    entity_id: EntityId,
+    component: &'static str,
+    missing: &'static str,
+) -> Result<&'a C, BehaviourError> {
+    view.get_component::<C>(entity_id)
+        .ok_or_else(|| BehaviourError::missing_component(entity_id, component, missing))
+}
+
+/// What a behaviour does with an entity it could not act on.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Leave the entity as it is and try again next tick.
+    Skip,
+    /// Put the entity back into its starting state, e.g. an idle lumberjack. Behaviours whose
+    /// entities have no state to reset skip them instead.
+    Reset,
+    /// Ignore the entity until it leaves the view.
+    Quarantine,
+}
+
+impl ErrorPolicy {
+    pub fn label(self) -> &'static str {
+        match self {
+            ErrorPolicy::Skip => "skip",
+            ErrorPolicy::Reset => "reset",
+            ErrorPolicy::Quarantine => "quarantine",
+        }
+    }
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "skip" => Ok(ErrorPolicy::Skip),
+            "reset" => Ok(ErrorPolicy::Reset),
+            "quarantine" => Ok(ErrorPolicy::Quarantine),
+            _ => Err(format!(
+                "Unknown error policy \"{}\", expected skip, reset or quarantine",
+                s
+            )),
+        }
+    }
+}
+
+/// Applies an `ErrorPolicy` to the entities a behaviour fails on and counts how often it does.
+pub struct ErrorHandler {
+    policy: ErrorPolicy,
+    quarantined: BTreeSet<EntityId>,
+    // Errors already logged, so that an entity failing the same way every tick is logged once.
+    logged: BTreeSet<(EntityId, ErrorKind)>,
+    // By error label.
+    counts: BTreeMap<&'static str, u64>,
+}
+
+impl ErrorHandler {
+    pub fn new(policy: ErrorPolicy) -> Self {
+        ErrorHandler {
+            policy,
+            quarantined: BTreeSet::new(),
+            logged: BTreeSet::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Releases quarantined entities that have left the view.
+    pub fn tick(&mut self, view: &View) {
+        for removed in view.iter_entities_removed() {
+            self.quarantined.remove(removed);
+            self.logged.retain(|(entity_id, _)| entity_id != removed);
+        }
+    }
+
+    pub fn is_quarantined(&self, entity_id: EntityId) -> bool {
+        self.quarantined.contains(&entity_id)
+    }
+
+    /// Counts `error`, logs it the first time the entity fails this way, and returns what the
+    /// behaviour should do with the entity.
+    pub fn handle(&mut self, error: &BehaviourError) -> ErrorPolicy {
+        *self.counts.entry(error.label()).or_insert(0) += 1;
+
+        if self.policy == ErrorPolicy::Quarantine {
+            self.quarantined.insert(error.entity_id);
+        }
+
+        if self.logged.insert((error.entity_id, error.kind.clone())) {
+            log_entity!(
+                Level::Warn,
+                error.entity_id,
+                error.component,
+                "{}, applying policy {}",
+                error,
+                self.policy.label()
+            );
+        }
+
+        self.policy
+    }
+
+    pub fn report(&self, behaviour: &'static str, metrics: &mut WorkerMetrics) {
+        for (error, count) in &self.counts {
+            metrics.set_counter(
+                "behaviour_errors",
+                &[
+                    ("behaviour", behaviour),
+                    ("error", *error),
+                    ("policy", self.policy.label()),
+                ],
+                *count,
+            );
+        }
+
+        metrics.set_gauge(
+            "quarantined_entities",
+            &[("behaviour", behaviour)],
+            self.quarantined.len() as f64,
+        );
+    }
+}
@@ -1,79 +1,220 @@
-use spatialos_sdk::worker::connection::{Connection, WorkerConnection};
-use spatialos_sdk::worker::metrics::Metrics;
-use std::time::{Duration, SystemTime};
+use crate::shared::metrics::WorkerMetrics;
+use crate::shared::utils::duration_secs;
+use std::thread;
+use std::time::{Duration, Instant};
 
-pub struct FpsTracker {
-    measurements: Vec<Duration>,
-    max_measurements: usize,
-    last: SystemTime,
+/// The longest frame `FramePacer` schedules, however low the target rate. Keeps the schedule
+/// within what an `Instant` can hold.
+const MAX_FRAME_SECS: f64 = 60.0;
 
-    metrics: Metrics,
+/// Measures how long frames take over the last `capacity` frames.
+pub struct FpsTracker {
+    // Ring buffer: once full, `next` points at the oldest frame.
+    frames: Vec<Duration>,
+    capacity: usize,
+    next: usize,
+    last: Instant,
 }
 
 impl FpsTracker {
-    pub fn new(max: usize) -> Self {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "FpsTracker needs room for at least one frame");
+
         FpsTracker {
-            measurements: Vec::new(),
-            max_measurements: max,
-            last: SystemTime::now(),
-            metrics: Metrics::new(),
+            frames: Vec::with_capacity(capacity),
+            capacity,
+            next: 0,
+            last: Instant::now(),
         }
     }
 
-    pub fn tick(&mut self, connection: &mut WorkerConnection) -> f64 {
-        self.record();
+    /// Marks the end of a frame and returns how long it took since the end of the previous one.
+    pub fn tick(&mut self) -> Duration {
+        let now = Instant::now();
+        let frame = now.duration_since(self.last);
+        self.last = now;
 
-        self.metrics.load = Some(self.get_fps());
-        connection.send_metrics(&self.metrics);
+        if self.frames.len() < self.capacity {
+            self.frames.push(frame);
+        } else {
+            self.frames[self.next] = frame;
+        }
 
-        self.measurements.last().unwrap().as_micros() as f64 / 1000.0
+        self.next = (self.next + 1) % self.capacity;
+        frame
     }
 
-    fn record(&mut self) {
-        let now = SystemTime::now();
-        let diff = now.duration_since(self.last).expect("Error");
-        self.measurements.push(diff);
+    /// Frames per second over the tracked frames.
+    pub fn fps(&self) -> f64 {
+        let total = duration_secs(self.frames.iter().sum());
 
-        if self.measurements.len() > self.max_measurements {
-            self.measurements.remove(0);
+        if total > 0.0 {
+            self.frames.len() as f64 / total
+        } else {
+            0.0
         }
+    }
 
-        self.last = now;
+    /// The frame time that `percentile` percent of the tracked frames took at most, e.g. the
+    /// median for 50.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let mut frames = self.frames.clone();
+        frames.sort();
+        nearest_rank(&frames, percentile)
     }
 
-    fn get_fps(&self) -> f64 {
-        if self.measurements.is_empty() {
-            return 0.0;
-        }
+    /// Records the frame rate as the worker load, along with the p50, p95 and p99 frame times.
+    pub fn report(&self, metrics: &mut WorkerMetrics) {
+        let fps = self.fps();
+        metrics.load = Some(fps);
+        metrics.set_gauge("fps", &[], fps);
 
-        let sum = self
-            .measurements
-            .iter()
-            .map(|duration| 1.0 / (f64::from(duration.subsec_micros()) / 1_000_000.0))
-            .fold(0.0, |sum, next| sum + next);
+        for (label, percentile) in &[("p50", 50.0), ("p95", 95.0), ("p99", 99.0)] {
+            let frame = self.percentile(*percentile);
+            metrics.set_gauge("frame_seconds", &[("quantile", *label)], duration_secs(frame));
+        }
+    }
+}
 
-        sum / self.measurements.len() as f64
+fn nearest_rank(sorted: &[Duration], percentile: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::from_secs(0);
     }
+
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.max(1).min(sorted.len()) - 1]
 }
 
-pub struct FpsLimiter {
-    target_frame_ms: f64
+/// Keeps frames to a target rate. Frames are paced against a fixed schedule rather than by
+/// sleeping a fixed amount, so time lost to one oversleep is taken out of the next frame.
+pub struct FramePacer {
+    // `None` runs uncapped.
+    frame: Option<Duration>,
+    deadline: Instant,
 }
 
-impl FpsLimiter {
-    pub fn new(target_framerate: f64) -> Self {
-        FpsLimiter {
-            target_frame_ms: 1000.0 / target_framerate
+impl FramePacer {
+    /// `None` runs uncapped, as fast as frames can be processed, e.g. for benchmarks. Rates below
+    /// one frame per `MAX_FRAME_SECS`, including zero and negative rates, run at that rate instead.
+    pub fn new(target_fps: Option<f64>) -> Self {
+        let frame = target_fps.map(|fps| {
+            let secs = if fps > 0.0 {
+                (1.0 / fps).min(MAX_FRAME_SECS)
+            } else {
+                MAX_FRAME_SECS
+            };
+            Duration::from_nanos((secs * 1_000_000_000.0).round() as u64)
+        });
+
+        FramePacer {
+            frame,
+            deadline: Instant::now(),
         }
     }
 
-    pub fn tick(&self, frame_time_ms: f64) {
-        let diff = self.target_frame_ms - frame_time_ms;
-
-        if diff > 0.0 {
-            ::std::thread::sleep(Duration::from_micros((diff * 1000.0).round() as i64 as u64));
+    /// Sleeps until the current frame's slot in the schedule is over.
+    pub fn wait(&mut self) {
+        let frame = match self.frame {
+            Some(frame) => frame,
+            None => return,
+        };
+
+        self.deadline += frame;
+        let now = Instant::now();
+
+        if self.deadline > now {
+            thread::sleep(self.deadline - now);
+        } else if now - self.deadline > frame {
+            // More than a frame behind, e.g. after a stall. Start a new schedule instead of
+            // rushing through frames to catch up.
+            self.deadline = now;
         }
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn millis(values: &[u64]) -> Vec<Duration> {
+        values.iter().map(|ms| Duration::from_millis(*ms)).collect()
+    }
+
+    #[test]
+    fn nearest_rank_of_no_frames_is_zero() {
+        assert_eq!(nearest_rank(&[], 50.0), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn nearest_rank_of_one_frame_is_that_frame() {
+        let frames = millis(&[16]);
+
+        for percentile in &[0.0, 50.0, 95.0, 99.0, 100.0] {
+            assert_eq!(nearest_rank(&frames, *percentile), Duration::from_millis(16));
+        }
+    }
+
+    #[test]
+    fn nearest_rank_picks_exact_ranks() {
+        let frames = millis(&(1..=100).collect::<Vec<u64>>());
+
+        assert_eq!(nearest_rank(&frames, 50.0), Duration::from_millis(50));
+        assert_eq!(nearest_rank(&frames, 95.0), Duration::from_millis(95));
+        assert_eq!(nearest_rank(&frames, 99.0), Duration::from_millis(99));
+        assert_eq!(nearest_rank(&frames, 100.0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn nearest_rank_rounds_up_between_ranks() {
+        let frames = millis(&[10, 20, 30, 40]);
 
+        assert_eq!(nearest_rank(&frames, 0.0), Duration::from_millis(10));
+        assert_eq!(nearest_rank(&frames, 50.0), Duration::from_millis(20));
+        assert_eq!(nearest_rank(&frames, 51.0), Duration::from_millis(30));
+        assert_eq!(nearest_rank(&frames, 95.0), Duration::from_millis(40));
+    }
+
+    #[test]
+    fn tracker_overwrites_the_oldest_frames_once_full() {
+        let mut tracker = FpsTracker::new(3);
+
+        for tenths in 1..=5 {
+            // Pretend the frame started this long ago, rather than sleeping through it.
+            tracker.last = Instant::now() - Duration::from_millis(100 * tenths);
+            tracker.tick();
+        }
+
+        let tenths = tracker
+            .frames
+            .iter()
+            .map(|frame| frame.as_millis() / 100)
+            .collect::<Vec<u128>>();
+
+        assert_eq!(tenths, vec![4, 5, 3]);
+        assert_eq!(tracker.next, 2);
+        assert_eq!(tracker.percentile(0.0).as_millis() / 100, 3);
+        assert_eq!(tracker.percentile(100.0).as_millis() / 100, 5);
+    }
+
+    #[test]
+    fn pacer_frame_follows_the_target_rate() {
+        assert_eq!(FramePacer::new(Some(50.0)).frame, Some(Duration::from_millis(20)));
+        assert_eq!(FramePacer::new(Some(0.01)).frame, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn pacer_runs_zero_and_negative_rates_at_the_slowest_rate() {
+        assert_eq!(FramePacer::new(Some(0.0)).frame, Some(Duration::from_secs(60)));
+        assert_eq!(FramePacer::new(Some(-30.0)).frame, Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn pacer_without_a_rate_is_uncapped() {
+        let mut pacer = FramePacer::new(None);
+        assert_eq!(pacer.frame, None);
+
+        let start = Instant::now();
+        pacer.wait();
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}
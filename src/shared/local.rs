@@ -0,0 +1,613 @@
+use crate::shared::connection::GameConnection;
+use crate::shared::generated::demo::{Fire, Headquarters, Lumberjack, MatchState, Tree, Wizard};
+use crate::shared::generated::improbable::{
+    EntityAcl, Metadata, Persistence, Position, WorkerRequirementSet,
+};
+use log::warn;
+use spatialos_sdk::worker::commands::{
+    CommandParameters, CreateEntityRequest, IncomingCommandRequest, OutgoingCommandRequest,
+};
+use spatialos_sdk::worker::component::{Component, ComponentData, ComponentId, UpdateParameters};
+use spatialos_sdk::worker::entity::Entity;
+use spatialos_sdk::worker::metrics::Metrics;
+use spatialos_sdk::worker::op::StatusCode;
+use spatialos_sdk::worker::view::View;
+use spatialos_sdk::worker::{Authority, EntityId, LogLevel, RequestId};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+type ViewOp = Box<dyn FnOnce(&mut View)>;
+
+/// An in-process stand-in for a SpatialOS runtime. Holds the world state and routes updates and
+/// commands between the `LocalConnection`s created from it.
+///
+/// Every worker sees every entity, authority is assigned from each entity's `EntityAcl` and
+/// component updates are always looped back to the sender, whatever their `UpdateParameters`.
+pub struct LocalRuntime {
+    world: Rc<RefCell<World>>,
+}
+
+impl LocalRuntime {
+    pub fn new() -> Self {
+        LocalRuntime {
+            world: Rc::new(RefCell::new(World {
+                entities: BTreeMap::new(),
+                workers: Vec::new(),
+                next_entity_id: 1,
+                next_request_id: 1,
+                pending_commands: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn add_entity(&self, entity_id: EntityId, entity: &Entity) {
+        self.world.borrow_mut().add_entity(entity_id, entity);
+    }
+
+    /// Connects a new worker which has the given layer as its only attribute besides its worker id.
+    pub fn connect(&self, worker_id: &str, layer: &str) -> LocalConnection {
+        let index = self
+            .world
+            .borrow_mut()
+            .add_worker(vec![layer.to_owned(), format!("workerId:{}", worker_id)]);
+
+        LocalConnection {
+            world: self.world.clone(),
+            index,
+            worker_id: worker_id.to_owned(),
+        }
+    }
+}
+
+pub struct LocalConnection {
+    world: Rc<RefCell<World>>,
+    index: usize,
+    worker_id: String,
+}
+
+impl GameConnection for LocalConnection {
+    fn get_worker_id(&self) -> &str {
+        &self.worker_id
+    }
+
+    fn get_worker_attributes(&self) -> Vec<String> {
+        self.world.borrow().workers[self.index].attributes.clone()
+    }
+
+    fn update_view(&mut self, view: &mut View) -> Result<(), String> {
+        let ops = ::std::mem::replace(
+            &mut self.world.borrow_mut().workers[self.index].inbox,
+            Vec::new(),
+        );
+
+        for op in ops {
+            op(view);
+        }
+
+        // The in-process world never drops a worker.
+        Ok(())
+    }
+
+    fn send_component_update<C: Component + 'static>(
+        &mut self,
+        entity_id: EntityId,
+        update: C::Update,
+        _params: UpdateParameters,
+    ) where
+        C::Update: Clone,
+    {
+        // `UpdateParameters` keeps its loopback flag private, so it cannot be honoured here. Every
+        // behaviour allows loopback anyway, as its own view only learns of its updates that way.
+        self.world
+            .borrow_mut()
+            .update_component::<C>(self.index, entity_id, update);
+    }
+
+    fn send_command_request<C: Component + 'static>(
+        &mut self,
+        entity_id: EntityId,
+        request: C::CommandRequest,
+        _timeout_millis: Option<u32>,
+        _params: CommandParameters,
+    ) -> RequestId<OutgoingCommandRequest> {
+        RequestId::new(
+            self.world
+                .borrow_mut()
+                .send_command_request::<C>(self.index, entity_id, request),
+        )
+    }
+
+    fn send_command_response<C: Component + 'static>(
+        &mut self,
+        request_id: RequestId<IncomingCommandRequest>,
+        response: C::CommandResponse,
+    ) {
+        let mut world = self.world.borrow_mut();
+        let pending = match world.pending_commands.remove(&request_id.id) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        let entity_id = pending.entity_id;
+        world.workers[pending.requester].inbox.push(Box::new(move |view: &mut View| {
+            view.add_command_response::<C>(
+                entity_id,
+                RequestId::new(request_id.id),
+                StatusCode::Success(response),
+            )
+        }));
+    }
+
+    fn send_command_failure(&mut self, request_id: RequestId<IncomingCommandRequest>, message: &str) {
+        let mut world = self.world.borrow_mut();
+        let pending = match world.pending_commands.remove(&request_id.id) {
+            Some(pending) => pending,
+            None => return,
+        };
+
+        world.workers[pending.requester]
+            .inbox
+            .push((pending.fail)(message.to_owned()));
+    }
+
+    /// Creates the entity straight away, with the next free id if none is given. No
+    /// `CreateEntityResponse` op is delivered for the returned request id, as nothing in the demo
+    /// waits for one.
+    fn send_create_entity_request(
+        &mut self,
+        entity: Entity,
+        entity_id: Option<EntityId>,
+        _timeout_millis: Option<u32>,
+    ) -> RequestId<CreateEntityRequest> {
+        let mut world = self.world.borrow_mut();
+
+        let entity_id = entity_id.unwrap_or_else(|| EntityId::new(world.next_entity_id));
+        world.add_entity(entity_id, &entity);
+
+        let request_id = world.next_request_id;
+        world.next_request_id += 1;
+        RequestId::new(request_id)
+    }
+
+    fn send_metrics(&mut self, _metrics: &Metrics) {}
+
+    fn send_log_message(
+        &mut self,
+        _level: LogLevel,
+        _logger_name: &str,
+        _message: &str,
+        _entity_id: Option<EntityId>,
+    ) {
+    }
+}
+
+struct World {
+    entities: BTreeMap<EntityId, LocalEntity>,
+    workers: Vec<LocalWorker>,
+    next_entity_id: i64,
+    next_request_id: u32,
+    pending_commands: HashMap<u32, PendingCommand>,
+}
+
+impl World {
+    fn add_worker(&mut self, attributes: Vec<String>) -> usize {
+        let index = self.workers.len();
+        self.workers.push(LocalWorker {
+            attributes,
+            inbox: Vec::new(),
+        });
+
+        let entity_ids = self.entities.keys().cloned().collect::<Vec<EntityId>>();
+        for entity_id in entity_ids {
+            self.send_entity(index, entity_id);
+            self.assign_authority(entity_id);
+        }
+
+        index
+    }
+
+    fn add_entity(&mut self, entity_id: EntityId, entity: &Entity) {
+        self.next_entity_id = ::std::cmp::max(self.next_entity_id, entity_id.id + 1);
+        self.entities.insert(
+            entity_id,
+            LocalEntity {
+                components: copy_components(entity),
+                authority: HashMap::new(),
+            },
+        );
+
+        for index in 0..self.workers.len() {
+            self.send_entity(index, entity_id);
+        }
+
+        self.assign_authority(entity_id);
+    }
+
+    fn send_entity(&mut self, index: usize, entity_id: EntityId) {
+        let components = &self.entities[&entity_id].components;
+        let inbox = &mut self.workers[index].inbox;
+
+        inbox.push(Box::new(move |view: &mut View| view.add_entity(entity_id)));
+        for component in components.values() {
+            inbox.push(component.add_op(entity_id));
+        }
+    }
+
+    /// Hands any unowned components of the entity to the first worker that satisfies its write ACL.
+    fn assign_authority(&mut self, entity_id: EntityId) {
+        let workers = &mut self.workers;
+        let entity = match self.entities.get_mut(&entity_id) {
+            Some(entity) => entity,
+            None => return,
+        };
+
+        let write_acl = match entity
+            .components
+            .get(&EntityAcl::ID)
+            .and_then(|acl| acl.as_any().downcast_ref::<EntityAcl>())
+        {
+            Some(acl) => acl.component_write_acl.clone(),
+            None => return,
+        };
+
+        for (component_id, component) in &entity.components {
+            if entity.authority.contains_key(component_id) {
+                continue;
+            }
+
+            let requirements = match write_acl.get(component_id) {
+                Some(requirements) => requirements,
+                None => continue,
+            };
+
+            let owner = workers
+                .iter()
+                .position(|worker| worker.satisfies(requirements));
+
+            if let Some(index) = owner {
+                entity.authority.insert(*component_id, index);
+                workers[index]
+                    .inbox
+                    .push(component.authority_op(entity_id, Authority::Authoritative));
+            }
+        }
+    }
+
+    fn update_component<C: Component + 'static>(
+        &mut self,
+        sender: usize,
+        entity_id: EntityId,
+        update: C::Update,
+    ) where
+        C::Update: Clone,
+    {
+        let entity = match self.entities.get_mut(&entity_id) {
+            Some(entity) => entity,
+            None => return,
+        };
+
+        if entity.authority.get(&C::ID) != Some(&sender) {
+            warn!(
+                "Dropping update for component {} on {:?} from a non-authoritative worker.",
+                C::ID,
+                entity_id
+            );
+            return;
+        }
+
+        let data = match entity
+            .components
+            .get_mut(&C::ID)
+            .and_then(|data| data.as_any_mut().downcast_mut::<C>())
+        {
+            Some(data) => data,
+            None => return,
+        };
+
+        data.merge(update.clone());
+
+        for worker in &mut self.workers {
+            let update = update.clone();
+            worker.inbox.push(Box::new(move |view: &mut View| {
+                view.update_component::<C>(entity_id, update)
+            }));
+        }
+    }
+
+    fn send_command_request<C: Component + 'static>(
+        &mut self,
+        sender: usize,
+        entity_id: EntityId,
+        request: C::CommandRequest,
+    ) -> u32 {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+
+        let respond = move |status: StatusCode<C::CommandResponse>| -> ViewOp {
+            Box::new(move |view: &mut View| {
+                view.add_command_response::<C>(entity_id, RequestId::new(request_id), status)
+            })
+        };
+
+        let receiver = self
+            .entities
+            .get(&entity_id)
+            .and_then(|entity| entity.authority.get(&C::ID).cloned());
+
+        match receiver {
+            Some(receiver) => {
+                self.pending_commands.insert(
+                    request_id,
+                    PendingCommand {
+                        requester: sender,
+                        entity_id,
+                        fail: Box::new(move |message| respond(StatusCode::ApplicationError(message))),
+                    },
+                );
+
+                self.workers[receiver].inbox.push(Box::new(move |view: &mut View| {
+                    view.add_command_request::<C>(entity_id, RequestId::new(request_id), request)
+                }));
+            }
+            None => {
+                let message = format!("No worker is authoritative over {:?}.", entity_id);
+                self.workers[sender]
+                    .inbox
+                    .push(respond(StatusCode::NotFound(message)));
+            }
+        }
+
+        request_id
+    }
+}
+
+struct LocalWorker {
+    attributes: Vec<String>,
+    inbox: Vec<ViewOp>,
+}
+
+impl LocalWorker {
+    fn satisfies(&self, requirements: &WorkerRequirementSet) -> bool {
+        requirements.attribute_set.iter().any(|set| {
+            set.attribute
+                .iter()
+                .all(|attribute| self.attributes.contains(attribute))
+        })
+    }
+}
+
+struct LocalEntity {
+    components: HashMap<ComponentId, Box<dyn StoredComponent>>,
+    authority: HashMap<ComponentId, usize>,
+}
+
+struct PendingCommand {
+    requester: usize,
+    entity_id: EntityId,
+    fail: Box<dyn FnOnce(String) -> ViewOp>,
+}
+
+/// Type-erased component data, so that the world can store every component of an entity together.
+trait StoredComponent {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn add_op(&self, entity_id: EntityId) -> ViewOp;
+    fn authority_op(&self, entity_id: EntityId, authority: Authority) -> ViewOp;
+}
+
+impl<C: Component + Clone + 'static> StoredComponent for C {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn add_op(&self, entity_id: EntityId) -> ViewOp {
+        let data = self.clone();
+        Box::new(move |view: &mut View| view.add_component::<C>(entity_id, data))
+    }
+
+    fn authority_op(&self, entity_id: EntityId, authority: Authority) -> ViewOp {
+        Box::new(move |view: &mut View| view.set_authority::<C>(entity_id, authority))
+    }
+}
+
+fn copy_components(entity: &Entity) -> HashMap<ComponentId, Box<dyn StoredComponent>> {
+    let mut components = HashMap::new();
+
+    copy_component::<Position>(entity, &mut components);
+    copy_component::<Metadata>(entity, &mut components);
+    copy_component::<Persistence>(entity, &mut components);
+    copy_component::<EntityAcl>(entity, &mut components);
+    copy_component::<Tree>(entity, &mut components);
+    copy_component::<Fire>(entity, &mut components);
+    copy_component::<Lumberjack>(entity, &mut components);
+    copy_component::<Headquarters>(entity, &mut components);
+    copy_component::<Wizard>(entity, &mut components);
+    copy_component::<MatchState>(entity, &mut components);
+
+    components
+}
+
+fn copy_component<C: Component + Clone + 'static>(
+    entity: &Entity,
+    components: &mut HashMap<ComponentId, Box<dyn StoredComponent>>,
+) {
+    if let Some(data) = entity.get::<C>() {
+        components.insert(C::ID, Box::new(data.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::generated::demo::{Chop, TreeCommandRequest, TreeCommandResponse, TreeUpdate};
+    use crate::shared::generated::improbable::Vector3d;
+    use crate::shared::templates;
+    use crate::shared::{CLIENT_LAYER, GAMELOGIC_LAYER};
+
+    const TREE: EntityId = EntityId { id: 1 };
+
+    fn origin() -> Vector3d {
+        Vector3d { x: 0.0, y: 0.0, z: 0.0 }
+    }
+
+    /// A runtime with one tree, a gamelogic worker and a client, and their caught-up views.
+    fn world() -> (LocalRuntime, LocalConnection, View, LocalConnection, View) {
+        let runtime = LocalRuntime::new();
+        runtime.add_entity(TREE, &templates::tree(&origin(), 5).unwrap());
+
+        let mut gamelogic = runtime.connect("gamelogic", GAMELOGIC_LAYER);
+        let mut client = runtime.connect("client", CLIENT_LAYER);
+
+        let mut gamelogic_view = View::new();
+        let mut client_view = View::new();
+        gamelogic.update_view(&mut gamelogic_view).unwrap();
+        client.update_view(&mut client_view).unwrap();
+
+        (runtime, gamelogic, gamelogic_view, client, client_view)
+    }
+
+    fn resources_left(view: &View) -> Option<u32> {
+        view.get_component::<Tree>(TREE).map(|tree| tree.resources_left)
+    }
+
+    #[test]
+    fn every_worker_sees_every_entity() {
+        let (_runtime, _gamelogic, gamelogic_view, _client, client_view) = world();
+
+        assert_eq!(resources_left(&gamelogic_view), Some(5));
+        assert_eq!(resources_left(&client_view), Some(5));
+    }
+
+    #[test]
+    fn authority_follows_the_write_acl() {
+        let (_runtime, _gamelogic, gamelogic_view, _client, client_view) = world();
+
+        assert!(gamelogic_view.is_authoritative::<Tree>(TREE));
+        assert!(gamelogic_view.is_authoritative::<Fire>(TREE));
+        assert!(!client_view.is_authoritative::<Tree>(TREE));
+    }
+
+    #[test]
+    fn created_entities_get_the_next_id_and_their_owner() {
+        let (_runtime, mut gamelogic, mut gamelogic_view, mut client, mut client_view) = world();
+
+        client.send_create_entity_request(templates::wizard(&origin(), false, "client").unwrap(), None, None);
+        gamelogic.update_view(&mut gamelogic_view).unwrap();
+        client.update_view(&mut client_view).unwrap();
+
+        let wizard = EntityId::new(2);
+        assert!(client_view.is_authoritative::<Wizard>(wizard));
+        assert!(gamelogic_view.get_component::<Wizard>(wizard).is_some());
+        assert!(!gamelogic_view.is_authoritative::<Wizard>(wizard));
+    }
+
+    #[test]
+    fn updates_are_looped_back_and_sent_to_every_worker() {
+        let (_runtime, mut gamelogic, mut gamelogic_view, mut client, mut client_view) = world();
+
+        let update = TreeUpdate {
+            resources_left: Some(3),
+            max_resources: None,
+        };
+        gamelogic.send_component_update::<Tree>(TREE, update, UpdateParameters::new());
+        gamelogic.update_view(&mut gamelogic_view).unwrap();
+        client.update_view(&mut client_view).unwrap();
+
+        assert_eq!(resources_left(&gamelogic_view), Some(3));
+        assert_eq!(resources_left(&client_view), Some(3));
+    }
+
+    #[test]
+    fn updates_from_non_authoritative_workers_are_dropped() {
+        let (_runtime, mut gamelogic, mut gamelogic_view, mut client, mut client_view) = world();
+
+        let update = TreeUpdate {
+            resources_left: Some(0),
+            max_resources: None,
+        };
+        client.send_component_update::<Tree>(TREE, update, UpdateParameters::new());
+        gamelogic.update_view(&mut gamelogic_view).unwrap();
+        client.update_view(&mut client_view).unwrap();
+
+        assert_eq!(resources_left(&gamelogic_view), Some(5));
+        assert_eq!(resources_left(&client_view), Some(5));
+    }
+
+    #[test]
+    fn commands_are_routed_to_the_authoritative_worker_and_answered() {
+        let (_runtime, mut gamelogic, mut gamelogic_view, mut client, mut client_view) = world();
+
+        let request_id = client.send_command_request::<Tree>(
+            TREE,
+            TreeCommandRequest::TryChop(Chop {}),
+            None,
+            CommandParameters::new(),
+        );
+        gamelogic.update_view(&mut gamelogic_view).unwrap();
+
+        let requests = gamelogic_view.get_command_requests::<Tree>(TREE).unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].0.id, request_id.id);
+
+        gamelogic.send_command_response::<Tree>(requests[0].0, TreeCommandResponse::TryChop(Chop {}));
+        client.update_view(&mut client_view).unwrap();
+
+        let succeeded = match client_view.get_command_response::<Tree>(TREE, RequestId::new(request_id.id)) {
+            Some(StatusCode::Success(_)) => true,
+            _ => false,
+        };
+        assert!(succeeded);
+    }
+
+    #[test]
+    fn command_failures_reach_the_requester() {
+        let (_runtime, mut gamelogic, mut gamelogic_view, mut client, mut client_view) = world();
+
+        let request_id = client.send_command_request::<Tree>(
+            TREE,
+            TreeCommandRequest::TryChop(Chop {}),
+            None,
+            CommandParameters::new(),
+        );
+        gamelogic.update_view(&mut gamelogic_view).unwrap();
+
+        let requests = gamelogic_view.get_command_requests::<Tree>(TREE).unwrap();
+        gamelogic.send_command_failure(requests[0].0, "Tree not available.");
+        client.update_view(&mut client_view).unwrap();
+
+        let message = match client_view.get_command_response::<Tree>(TREE, RequestId::new(request_id.id)) {
+            Some(StatusCode::ApplicationError(message)) => Some(message.to_string()),
+            _ => None,
+        };
+        assert_eq!(message.as_ref().map(String::as_str), Some("Tree not available."));
+    }
+
+    #[test]
+    fn commands_without_an_authoritative_worker_are_not_found() {
+        let runtime = LocalRuntime::new();
+        let mut client = runtime.connect("client", CLIENT_LAYER);
+        runtime.add_entity(TREE, &templates::tree(&origin(), 5).unwrap());
+
+        let request_id = client.send_command_request::<Tree>(
+            TREE,
+            TreeCommandRequest::TryChop(Chop {}),
+            None,
+            CommandParameters::new(),
+        );
+
+        let mut client_view = View::new();
+        client.update_view(&mut client_view).unwrap();
+
+        let not_found = match client_view.get_command_response::<Tree>(TREE, RequestId::new(request_id.id)) {
+            Some(StatusCode::NotFound(_)) => true,
+            _ => false,
+        };
+        assert!(not_found);
+    }
+}
@@ -0,0 +1,348 @@
+use crate::shared::config::WorkerConfig;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spatialos_sdk::worker::{EntityId, LogLevel};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Records waiting to be forwarded to SpatialOS are dropped, oldest first, beyond this many.
+const MAX_FORWARDED: usize = 1000;
+
+/// Logs a record that is about a single entity, e.g.
+/// `log_entity!(Level::Warn, entity_id, "Lumberjack", "No HQs found")`.
+#[macro_export]
+macro_rules! log_entity {
+    ($level:expr, $entity_id:expr, $component:expr, $($arg:tt)+) => {{
+        $crate::shared::logging::set_entity(Some(($entity_id, $component)));
+        log::log!($level, $($arg)+);
+        $crate::shared::logging::set_entity(None);
+    }};
+}
+
+/// Per-module log levels in the form `info,gamelogic::behaviors::trees=debug`. A bare level sets
+/// the default; `<module>=<level>` applies to that module and everything inside it.
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    default: LevelFilter,
+    // Longest module first, so that the most specific directive wins.
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    pub fn level_for(&self, target: &str) -> LevelFilter {
+        self.directives
+            .iter()
+            .find(|(module, _)| {
+                target == module
+                    || (target.starts_with(module.as_str())
+                        && target[module.len()..].starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter {
+            default: LevelFilter::Info,
+            directives: Vec::new(),
+        };
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let mut parts = directive.splitn(2, '=');
+            let first = parts.next().unwrap_or("");
+
+            match parts.next() {
+                Some(level) => filter
+                    .directives
+                    .push((first.to_owned(), parse_level(level)?)),
+                None => filter.default = parse_level(first)?,
+            }
+        }
+
+        filter
+            .directives
+            .sort_by(|a, b| b.0.len().cmp(&a.0.len()));
+
+        Ok(filter)
+    }
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level
+        .trim()
+        .parse()
+        .map_err(|_| format!("Unknown log level \"{}\"", level))
+}
+
+/// A record waiting to be sent to the SpatialOS worker log.
+#[derive(Debug, Clone)]
+pub struct ForwardedLog {
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    pub entity_id: Option<EntityId>,
+}
+
+impl ForwardedLog {
+    pub fn spatialos_level(&self) -> LogLevel {
+        match self.level {
+            Level::Error => LogLevel::Error,
+            Level::Warn => LogLevel::Warn,
+            Level::Info => LogLevel::Info,
+            Level::Debug | Level::Trace => LogLevel::Debug,
+        }
+    }
+}
+
+#[derive(Default)]
+struct LogContext {
+    tick: Option<u64>,
+    behaviour: Option<&'static str>,
+    entity: Option<(EntityId, &'static str)>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default());
+
+    // Workers are single threaded, so records are forwarded from the thread that drains them.
+    static FORWARDED: RefCell<VecDeque<ForwardedLog>> = RefCell::new(VecDeque::new());
+}
+
+/// Tags every following record on this thread with the simulation tick.
+pub fn set_tick(tick: u64) {
+    CONTEXT.with(|context| context.borrow_mut().tick = Some(tick));
+}
+
+/// Tags every following record on this thread with the behaviour that is running.
+pub fn set_behaviour(behaviour: Option<&'static str>) {
+    CONTEXT.with(|context| context.borrow_mut().behaviour = behaviour);
+}
+
+/// Used by `log_entity!`.
+#[doc(hidden)]
+pub fn set_entity(entity: Option<(EntityId, &'static str)>) {
+    CONTEXT.with(|context| context.borrow_mut().entity = entity);
+}
+
+/// Takes the records logged on this thread since the last call that should go to SpatialOS.
+pub fn take_forwarded() -> VecDeque<ForwardedLog> {
+    FORWARDED.with(|forwarded| ::std::mem::replace(&mut *forwarded.borrow_mut(), VecDeque::new()))
+}
+
+struct Logger {
+    filter: LogFilter,
+    stderr: bool,
+    file: Option<Mutex<LineWriter<File>>>,
+    forward: bool,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let message = record.args().to_string();
+
+        let line = CONTEXT.with(|context| {
+            let context = context.borrow();
+            format_line(record, &message, &context)
+        });
+
+        if self.stderr {
+            let _ = writeln!(io::stderr(), "{}", line);
+        }
+
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{}", line);
+            }
+        }
+
+        if self.forward {
+            let entity_id = CONTEXT.with(|context| context.borrow().entity.map(|(id, _)| id));
+
+            FORWARDED.with(|forwarded| {
+                let mut forwarded = forwarded.borrow_mut();
+                if forwarded.len() >= MAX_FORWARDED {
+                    forwarded.pop_front();
+                }
+
+                forwarded.push_back(ForwardedLog {
+                    level: record.level(),
+                    target: record.target().to_owned(),
+                    message,
+                    entity_id,
+                });
+            });
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(ref file) = self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+/// `ts=<unix seconds> level=<level> target=<module> [tick=..] [behaviour=..] [entity=..]
+/// [component=..] msg="<message>"`
+fn format_line(record: &Record, message: &str, context: &LogContext) -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    let mut line = format!(
+        "ts={}.{:03} level={} target={}",
+        now.as_secs(),
+        now.subsec_millis(),
+        record.level(),
+        record.target()
+    );
+
+    if let Some(tick) = context.tick {
+        let _ = write!(line, " tick={}", tick);
+    }
+
+    if let Some(behaviour) = context.behaviour {
+        let _ = write!(line, " behaviour={}", behaviour);
+    }
+
+    if let Some((entity_id, component)) = context.entity {
+        let _ = write!(line, " entity={} component={}", entity_id.id, component);
+    }
+
+    let _ = write!(line, " msg={:?}", message);
+    line
+}
+
+/// Installs the global logger described by the `log_*` settings in `config`.
+pub fn init(config: &WorkerConfig) -> Result<(), String> {
+    let file = match config.log_file {
+        Some(ref path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Could not open log file {}: {}", path.display(), e))?;
+
+            Some(Mutex::new(LineWriter::new(file)))
+        }
+        None => None,
+    };
+
+    let logger = Logger {
+        filter: config.log_level.clone(),
+        stderr: config.log_to_stderr,
+        file,
+        forward: config.log_to_spatialos,
+    };
+
+    log::set_boxed_logger(Box::new(logger)).map_err(|e| e.to_string())?;
+    log::set_max_level(config.log_level.max_level());
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(s: &str) -> LogFilter {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn defaults_to_info() {
+        let filter = filter("");
+        assert_eq!(filter.level_for("gamelogic::behaviors::trees"), LevelFilter::Info);
+        assert_eq!(filter.max_level(), LevelFilter::Info);
+    }
+
+    #[test]
+    fn a_bare_level_sets_the_default() {
+        let filter = filter("warn,gamelogic=debug");
+        assert_eq!(filter.level_for("client::behaviors::wizards"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("gamelogic"), LevelFilter::Debug);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn the_longest_matching_module_wins() {
+        for directives in &[
+            "gamelogic=warn,gamelogic::behaviors=debug,gamelogic::behaviors::trees=trace",
+            "gamelogic::behaviors::trees=trace,gamelogic::behaviors=debug,gamelogic=warn",
+        ] {
+            let filter = filter(directives);
+            assert_eq!(filter.level_for("gamelogic::behaviors::trees"), LevelFilter::Trace);
+            assert_eq!(filter.level_for("gamelogic::behaviors::hq"), LevelFilter::Debug);
+            assert_eq!(filter.level_for("gamelogic::main"), LevelFilter::Warn);
+        }
+    }
+
+    #[test]
+    fn modules_only_match_at_a_path_boundary() {
+        let filter = filter("error,gamelogic::behaviors::tree=trace");
+        assert_eq!(filter.level_for("gamelogic::behaviors::tree"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("gamelogic::behaviors::tree::growth"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("gamelogic::behaviors::trees"), LevelFilter::Error);
+        assert_eq!(filter.level_for("gamelogic::behaviors"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn rejects_unknown_levels() {
+        let error = "loud".parse::<LogFilter>().unwrap_err();
+        assert_eq!(error, "Unknown log level \"loud\"");
+
+        let error = "info,gamelogic=verbose".parse::<LogFilter>().unwrap_err();
+        assert_eq!(error, "Unknown log level \"verbose\"");
+    }
+
+    #[test]
+    fn drops_the_oldest_forwarded_records_once_full() {
+        let logger = Logger {
+            filter: filter("info"),
+            stderr: false,
+            file: None,
+            forward: true,
+        };
+
+        for i in 0..MAX_FORWARDED + 2 {
+            logger.log(
+                &Record::builder()
+                    .level(Level::Info)
+                    .target("test")
+                    .args(format_args!("record {}", i))
+                    .build(),
+            );
+        }
+
+        let forwarded = take_forwarded();
+        assert_eq!(forwarded.len(), MAX_FORWARDED);
+        assert_eq!(forwarded.front().unwrap().message, "record 2");
+        assert_eq!(forwarded.back().unwrap().message, format!("record {}", MAX_FORWARDED + 1));
+        assert!(take_forwarded().is_empty());
+    }
+}
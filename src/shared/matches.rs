@@ -0,0 +1,95 @@
+use crate::shared::connection::GameConnection;
+use crate::shared::generated::demo::{MatchPhase, MatchState};
+use crate::shared::metrics::WorkerMetrics;
+use crate::shared::scheduler::{Context, System};
+use log::debug;
+use spatialos_sdk::worker::view::{View, ViewQuery};
+use spatialos_sdk::worker::EntityId;
+
+/// Follows the match state, so that behaviours can pause between rounds.
+#[derive(Default)]
+pub struct MatchTracker {
+    entity_id: Option<EntityId>,
+    state: Option<MatchState>,
+}
+
+impl MatchTracker {
+    pub fn new() -> Self {
+        MatchTracker::default()
+    }
+
+    pub fn tick(&mut self, view: &View) {
+        match view.query::<MatchStateQuery>().next() {
+            Some(current) => {
+                let changed = self.state.as_ref().map_or(true, |state| {
+                    state.round != current.state.round || state.phase != current.state.phase
+                });
+
+                if changed {
+                    debug!("Round {} is now {:?}", current.state.round, current.state.phase);
+                }
+
+                self.entity_id = Some(current.entity_id);
+                self.state = Some(current.state.clone());
+            }
+            None => {
+                self.entity_id = None;
+                self.state = None;
+            }
+        }
+    }
+
+    pub fn entity_id(&self) -> Option<EntityId> {
+        self.entity_id
+    }
+
+    pub fn state(&self) -> Option<&MatchState> {
+        self.state.as_ref()
+    }
+
+    /// Whether a round is being played. Without a match state in the view, as with snapshots that
+    /// predate matches, the game never ends.
+    pub fn in_play(&self) -> bool {
+        self.state
+            .as_ref()
+            .map_or(true, |state| state.phase == MatchPhase::PLAYING)
+    }
+
+    pub fn report(&self, metrics: &mut WorkerMetrics) {
+        if let Some(ref state) = self.state {
+            metrics.set_gauge("match_round", &[], f64::from(state.round));
+        }
+    }
+}
+
+impl<C: GameConnection + 'static> System<C> for MatchTracker {
+    fn name(&self) -> &'static str {
+        "match_state"
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        MatchTracker::tick(self, ctx.view);
+    }
+
+    fn report(&self, _view: &View, metrics: &mut WorkerMetrics) {
+        MatchTracker::report(self, metrics);
+    }
+}
+
+struct MatchStateQuery<'a> {
+    entity_id: EntityId,
+    state: &'a MatchState,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for MatchStateQuery<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<MatchState>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        MatchStateQuery {
+            entity_id,
+            state: view.get_component::<MatchState>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
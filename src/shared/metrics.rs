@@ -0,0 +1,177 @@
+use spatialos_sdk::worker::metrics::{HistogramMetric, Metrics};
+use spatialos_sdk::worker::op::StatusCode;
+use std::collections::BTreeMap;
+
+/// Bucket upper bounds, in seconds, for histograms of how long something took.
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1,
+];
+
+/// A metric name plus its labels, e.g. `lumberjacks{action="idle"}`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MetricKey {
+    pub name: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+}
+
+impl MetricKey {
+    fn new(name: &'static str, labels: &[(&'static str, &str)]) -> Self {
+        MetricKey {
+            name,
+            labels: labels
+                .iter()
+                .map(|(label, value)| (*label, (*value).to_owned()))
+                .collect(),
+        }
+    }
+
+    /// SpatialOS metrics have no labels, so label values are appended to the name instead, e.g.
+    /// `lumberjacks.idle`.
+    pub fn flat_name(&self) -> String {
+        let mut name = self.name.to_owned();
+
+        for (_, value) in &self.labels {
+            name.push('.');
+            name.push_str(value);
+        }
+
+        name
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub bounds: &'static [f64],
+    /// Observations per bucket, not cumulative. The last entry counts everything above the
+    /// largest bound.
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+    // Observations made since the last report to SpatialOS.
+    unreported: Vec<f64>,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Histogram {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+            unreported: Vec::new(),
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+        self.unreported.push(value);
+    }
+}
+
+/// Everything a worker measures about itself. Systems fill it in when asked to report and the
+/// scheduler periodically sends it to SpatialOS.
+#[derive(Debug, Default)]
+pub struct WorkerMetrics {
+    pub load: Option<f64>,
+    gauges: BTreeMap<MetricKey, f64>,
+    counters: BTreeMap<MetricKey, f64>,
+    histograms: BTreeMap<MetricKey, Histogram>,
+}
+
+impl WorkerMetrics {
+    pub fn new() -> Self {
+        WorkerMetrics::default()
+    }
+
+    pub fn set_gauge(&mut self, name: &'static str, labels: &[(&'static str, &str)], value: f64) {
+        self.gauges.insert(MetricKey::new(name, labels), value);
+    }
+
+    /// Forgets every gauge with this name, for gauges whose label values come and go.
+    pub fn clear_gauges(&mut self, name: &'static str) {
+        let stale = self
+            .gauges
+            .keys()
+            .filter(|key| key.name == name)
+            .cloned()
+            .collect::<Vec<MetricKey>>();
+
+        for key in stale {
+            self.gauges.remove(&key);
+        }
+    }
+
+    /// Sets a counter to the running total kept by the caller.
+    pub fn set_counter(&mut self, name: &'static str, labels: &[(&'static str, &str)], total: u64) {
+        self.counters
+            .insert(MetricKey::new(name, labels), total as f64);
+    }
+
+    pub fn observe(
+        &mut self,
+        name: &'static str,
+        labels: &[(&'static str, &str)],
+        bounds: &'static [f64],
+        value: f64,
+    ) {
+        self.histograms
+            .entry(MetricKey::new(name, labels))
+            .or_insert_with(|| Histogram::new(bounds))
+            .observe(value);
+    }
+
+    pub fn gauges(&self) -> impl Iterator<Item = (&MetricKey, f64)> {
+        self.gauges.iter().map(|(key, value)| (key, *value))
+    }
+
+    pub fn counters(&self) -> impl Iterator<Item = (&MetricKey, f64)> {
+        self.counters.iter().map(|(key, value)| (key, *value))
+    }
+
+    pub fn histograms(&self) -> impl Iterator<Item = (&MetricKey, &Histogram)> {
+        self.histograms.iter()
+    }
+
+    /// Builds the SpatialOS metrics. Counters are sent as gauges of their total and histograms
+    /// only carry the observations made since the previous call.
+    pub fn to_spatialos(&mut self) -> Metrics {
+        let mut metrics = Metrics::new();
+        metrics.load = self.load;
+
+        for (key, value) in self.gauges.iter().chain(self.counters.iter()) {
+            metrics.gauge_metrics.insert(key.flat_name(), *value);
+        }
+
+        for (key, histogram) in self.histograms.iter_mut() {
+            let mut metric = HistogramMetric::new(histogram.bounds);
+            for value in histogram.unreported.drain(..) {
+                metric.add_sample(value);
+            }
+
+            metrics.histogram_metrics.insert(key.flat_name(), metric);
+        }
+
+        metrics
+    }
+}
+
+/// A short label for how a command ended, e.g. `timeout`.
+pub fn status_label<T>(status: &StatusCode<T>) -> &'static str {
+    match status {
+        StatusCode::Success(_) => "success",
+        StatusCode::Timeout(_) => "timeout",
+        StatusCode::NotFound(_) => "not_found",
+        StatusCode::AuthorityLost(_) => "authority_lost",
+        StatusCode::PermissionDenied(_) => "permission_denied",
+        StatusCode::ApplicationError(_) => "application_error",
+        StatusCode::InternalError(_) => "internal_error",
+    }
+}
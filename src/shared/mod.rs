@@ -1,9 +1,24 @@
+pub mod clock;
+pub mod config;
 pub mod connection;
+pub mod errors;
 pub mod fps;
 pub mod generated;
+pub mod local;
+pub mod logging;
+pub mod matches;
+pub mod metrics;
 pub mod opt;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod scheduler;
+pub mod scoreboard;
+pub mod shutdown;
+pub mod snapshot;
+pub mod spatial;
 pub mod templates;
+pub mod trees;
 pub mod utils;
 
-const GAMELOGIC_LAYER: &str = "gamelogic";
-const CLIENT_LAYER: &str = "client";
+pub const GAMELOGIC_LAYER: &str = "gamelogic";
+pub const CLIENT_LAYER: &str = "client";
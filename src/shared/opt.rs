@@ -1,3 +1,7 @@
+use crate::shared::config::Transport;
+use crate::shared::errors::ErrorPolicy;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -9,6 +13,120 @@ pub struct Opt {
     #[structopt(long = "evil")]
     pub evil: bool,
 
+    #[structopt(name = "SEED", long = "seed")]
+    pub seed: Option<u64>,
+
+    /// TOML or JSON file with connection settings. Defaults to $RUST_LDN_CONFIG.
+    #[structopt(long = "config", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// tcp or raknet. Defaults to tcp for the receptionist and raknet for the locator.
+    #[structopt(long = "transport")]
+    pub transport: Option<Transport>,
+
+    /// true or false. Defaults to true for the locator and false for the receptionist.
+    #[structopt(long = "use-external-ip")]
+    pub use_external_ip: Option<bool>,
+
+    #[structopt(long = "internal-serialization")]
+    pub internal_serialization: Option<bool>,
+
+    #[structopt(long = "connection-timeout-ms")]
+    pub connection_timeout_ms: Option<u64>,
+
+    /// Only applies to RakNet.
+    #[structopt(long = "heartbeat-timeout-ms")]
+    pub heartbeat_timeout_ms: Option<u32>,
+
+    #[structopt(long = "command-timeout-ms")]
+    pub command_timeout_ms: Option<u32>,
+
+    /// How many times a lumberjack resends a chop request that got no answer.
+    #[structopt(long = "chop-retries")]
+    pub chop_retries: Option<u32>,
+
+    /// Delay before the first resend of a chop request. Doubles with every failed attempt.
+    #[structopt(long = "chop-retry-backoff-ms")]
+    pub chop_retry_backoff_ms: Option<u64>,
+
+    #[structopt(long = "chop-retry-max-backoff-ms")]
+    pub chop_retry_max_backoff_ms: Option<u64>,
+
+    /// How much wood a lumberjack chops before taking it back to an HQ.
+    #[structopt(long = "carry-capacity")]
+    pub carry_capacity: Option<u32>,
+
+    /// Seconds before a depleted tree grows back. 0 disables regrowth.
+    #[structopt(long = "tree-regrowth-secs")]
+    pub tree_regrowth_secs: Option<u32>,
+
+    /// A round ends once a team's score reaches this. 0 disables it.
+    #[structopt(long = "match-score-target")]
+    pub match_score_target: Option<u32>,
+
+    /// A round ends after this many simulated seconds. 0 disables it.
+    #[structopt(long = "match-time-limit-secs")]
+    pub match_time_limit_secs: Option<u32>,
+
+    /// Whether a round ends once no tree has resources left, true or false.
+    #[structopt(long = "match-ends-when-trees-exhausted")]
+    pub match_ends_when_trees_exhausted: Option<bool>,
+
+    #[structopt(long = "match-intermission-secs")]
+    pub match_intermission_secs: Option<u64>,
+
+    /// A level (off, error, warn, info, debug or trace), optionally followed by per-module
+    /// levels, e.g. "info,gamelogic::behaviors::trees=debug".
+    #[structopt(long = "log-level")]
+    pub log_level: Option<String>,
+
+    /// Also append log records to this file.
+    #[structopt(long = "log-file", parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
+    #[structopt(long = "log-to-stderr")]
+    pub log_to_stderr: Option<bool>,
+
+    /// Forward log records to the SpatialOS worker log, true or false.
+    #[structopt(long = "log-to-spatialos")]
+    pub log_to_spatialos: Option<bool>,
+
+    /// What behaviours do with an entity they cannot act on: skip, reset or quarantine.
+    #[structopt(long = "error-policy")]
+    pub error_policy: Option<ErrorPolicy>,
+
+    #[structopt(long = "target-fps")]
+    pub target_fps: Option<f64>,
+
+    /// Run frames back to back, ignoring the target frame rate, e.g. for benchmarks.
+    #[structopt(long = "uncapped")]
+    pub uncapped: bool,
+
+    /// Serve metrics for Prometheus on this address, e.g. 127.0.0.1:9100. Needs the `prometheus`
+    /// feature.
+    #[structopt(long = "metrics-addr")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// How many times to retry connecting before giving up.
+    #[structopt(long = "reconnect-retries", default_value = "10")]
+    pub reconnect_retries: u32,
+
+    /// Delay before the first retry. Doubles with every failed attempt.
+    #[structopt(long = "reconnect-backoff-ms", default_value = "500")]
+    pub reconnect_backoff_ms: u64,
+
+    /// Upper bound on the delay between two attempts.
+    #[structopt(long = "reconnect-max-backoff-ms", default_value = "30000")]
+    pub reconnect_max_backoff_ms: u64,
+
+    /// Randomly stretch or shrink each delay by up to this fraction of itself.
+    #[structopt(long = "reconnect-jitter", default_value = "0.25")]
+    pub reconnect_jitter: f64,
+
+    /// Give up once this much time has passed since the first attempt.
+    #[structopt(long = "reconnect-max-duration-secs", default_value = "300")]
+    pub reconnect_max_duration_secs: u64,
+
     #[structopt(subcommand)]
     pub command: Command,
 }
@@ -29,10 +147,32 @@ pub enum Command {
 
     #[structopt(name = "locator")]
     Locator {
-        #[structopt(name = "LOCATOR_TOKEN", long = "locator-token", short = "t")]
-        token: String,
+        #[structopt(flatten)]
+        locator: LocatorOpt,
+
+        /// Connect to the deployment with exactly this name.
+        #[structopt(long = "deployment-name")]
+        deployment_name: Option<String>,
+
+        /// Connect to a deployment whose description contains `tag:<tag>`. The locator does not
+        /// report deployment tags themselves, and other words in the description are ignored.
+        #[structopt(long = "deployment-tag")]
+        deployment_tag: Option<String>,
+    },
 
-        #[structopt(name = "PROJECT_NAME", long = "project-name", short = "n")]
-        project_name: String,
+    /// Prints the deployments the locator offers and exits.
+    #[structopt(name = "list-deployments")]
+    ListDeployments {
+        #[structopt(flatten)]
+        locator: LocatorOpt,
     },
 }
+
+#[derive(Debug, StructOpt)]
+pub struct LocatorOpt {
+    #[structopt(name = "LOCATOR_TOKEN", long = "locator-token", short = "t")]
+    pub token: String,
+
+    #[structopt(name = "PROJECT_NAME", long = "project-name", short = "n")]
+    pub project_name: String,
+}
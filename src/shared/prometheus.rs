@@ -0,0 +1,233 @@
+use crate::shared::metrics::{MetricKey, WorkerMetrics};
+use log::{debug, info, warn};
+use std::fmt::Write as FmtWrite;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Every metric name gets this prefix, so that they are easy to tell apart in a shared Prometheus.
+const PREFIX: &str = "rust_ldn_";
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Scrapes that take longer than this to send their request are dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the latest metrics the scheduler published at `/metrics`, in the Prometheus text format.
+/// Cloning it shares the same page, so it can outlive a scheduler across reconnects.
+#[derive(Clone)]
+pub struct MetricsEndpoint {
+    page: Arc<Mutex<String>>,
+}
+
+impl MetricsEndpoint {
+    /// Starts serving on `addr` from a background thread, if an address is set.
+    pub fn start(addr: Option<SocketAddr>) -> Result<Option<Self>, String> {
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return Ok(None),
+        };
+
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| format!("Could not serve metrics on {}: {}", addr, e))?;
+
+        let endpoint = MetricsEndpoint {
+            page: Arc::new(Mutex::new(String::new())),
+        };
+
+        let page = endpoint.page.clone();
+        thread::Builder::new()
+            .name("metrics".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.and_then(|stream| respond(stream, &page));
+
+                    if let Err(e) = result {
+                        debug!("Metrics scrape failed: {}", e);
+                    }
+                }
+            })
+            .map_err(|e| format!("Could not start the metrics thread: {}", e))?;
+
+        info!("Serving Prometheus metrics on http://{}/metrics", addr);
+        Ok(Some(endpoint))
+    }
+
+    pub fn publish(&self, metrics: &WorkerMetrics) {
+        let page = render(metrics);
+
+        match self.page.lock() {
+            Ok(mut current) => *current = page,
+            Err(_) => warn!("Metrics page lock is poisoned, not publishing"),
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, page: &Mutex<String>) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    // Only the request line matters, and it fits comfortably in the first read.
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..read]);
+    let path = request.split_whitespace().nth(1).unwrap_or("");
+
+    let (status, body) = if path == "/metrics" {
+        let body = page.lock().map(|page| page.clone()).unwrap_or_default();
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", "Metrics are served at /metrics\n".to_owned())
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    )?;
+
+    stream.flush()
+}
+
+/// Writes `metrics` in the Prometheus text exposition format.
+pub fn render(metrics: &WorkerMetrics) -> String {
+    let mut page = String::new();
+
+    if let Some(load) = metrics.load {
+        let _ = writeln!(page, "# TYPE {}load gauge", PREFIX);
+        let _ = writeln!(page, "{}load {}", PREFIX, load);
+    }
+
+    let mut last_name = None;
+    for (key, value) in metrics.gauges() {
+        write_type(&mut page, &mut last_name, key.name, "", "gauge");
+        write_sample(&mut page, key, "", None, value);
+    }
+
+    let mut last_name = None;
+    for (key, value) in metrics.counters() {
+        write_type(&mut page, &mut last_name, key.name, "_total", "counter");
+        write_sample(&mut page, key, "_total", None, value);
+    }
+
+    let mut last_name = None;
+    for (key, histogram) in metrics.histograms() {
+        write_type(&mut page, &mut last_name, key.name, "", "histogram");
+
+        // Prometheus buckets are cumulative.
+        let mut cumulative = 0;
+        for (bound, count) in histogram.bounds.iter().zip(&histogram.counts) {
+            cumulative += count;
+            let le = bound.to_string();
+            write_sample(&mut page, key, "_bucket", Some(&le), cumulative as f64);
+        }
+
+        write_sample(&mut page, key, "_bucket", Some("+Inf"), histogram.count as f64);
+        write_sample(&mut page, key, "_sum", None, histogram.sum);
+        write_sample(&mut page, key, "_count", None, histogram.count as f64);
+    }
+
+    page
+}
+
+/// Metrics are sorted by name, so the type line only needs writing when the name changes.
+fn write_type(
+    page: &mut String,
+    last_name: &mut Option<&'static str>,
+    name: &'static str,
+    suffix: &str,
+    typ: &str,
+) {
+    if *last_name != Some(name) {
+        let _ = writeln!(page, "# TYPE {}{}{} {}", PREFIX, name, suffix, typ);
+        *last_name = Some(name);
+    }
+}
+
+fn write_sample(page: &mut String, key: &MetricKey, suffix: &str, le: Option<&str>, value: f64) {
+    let _ = write!(page, "{}{}{}", PREFIX, key.name, suffix);
+
+    let labels = key
+        .labels
+        .iter()
+        .map(|(label, value)| (*label, value.as_str()))
+        .chain(le.map(|le| ("le", le)))
+        .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+        .collect::<Vec<String>>();
+
+    if !labels.is_empty() {
+        let _ = write!(page, "{{{}}}", labels.join(","));
+    }
+
+    let _ = writeln!(page, " {}", value);
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_label_values() {
+        let mut metrics = WorkerMetrics::new();
+        metrics.set_gauge("odd", &[("value", "a\\b\"c\nd")], 1.0);
+
+        assert_eq!(
+            render(&metrics),
+            "# TYPE rust_ldn_odd gauge\nrust_ldn_odd{value=\"a\\\\b\\\"c\\nd\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn writes_one_type_line_per_metric_name() {
+        let mut metrics = WorkerMetrics::new();
+        metrics.load = Some(60.0);
+        metrics.set_gauge("lumberjacks", &[("action", "idle")], 3.0);
+        metrics.set_gauge("lumberjacks", &[("action", "waiting")], 2.0);
+        metrics.set_counter("tree_chops", &[("result", "granted")], 7);
+        metrics.set_counter("trees_regrown", &[], 1);
+
+        assert_eq!(
+            render(&metrics),
+            "# TYPE rust_ldn_load gauge\n\
+             rust_ldn_load 60\n\
+             # TYPE rust_ldn_lumberjacks gauge\n\
+             rust_ldn_lumberjacks{action=\"idle\"} 3\n\
+             rust_ldn_lumberjacks{action=\"waiting\"} 2\n\
+             # TYPE rust_ldn_tree_chops_total counter\n\
+             rust_ldn_tree_chops_total{result=\"granted\"} 7\n\
+             # TYPE rust_ldn_trees_regrown_total counter\n\
+             rust_ldn_trees_regrown_total 1\n"
+        );
+    }
+
+    #[test]
+    fn writes_cumulative_histogram_buckets() {
+        const BOUNDS: &[f64] = &[0.125, 1.0];
+
+        let mut metrics = WorkerMetrics::new();
+        for value in &[0.0625, 0.5, 2.0] {
+            metrics.observe("tick_seconds", &[("system", "trees")], BOUNDS, *value);
+        }
+
+        assert_eq!(
+            render(&metrics),
+            "# TYPE rust_ldn_tick_seconds histogram\n\
+             rust_ldn_tick_seconds_bucket{system=\"trees\",le=\"0.125\"} 1\n\
+             rust_ldn_tick_seconds_bucket{system=\"trees\",le=\"1\"} 2\n\
+             rust_ldn_tick_seconds_bucket{system=\"trees\",le=\"+Inf\"} 3\n\
+             rust_ldn_tick_seconds_sum{system=\"trees\"} 2.5625\n\
+             rust_ldn_tick_seconds_count{system=\"trees\"} 3\n"
+        );
+    }
+}
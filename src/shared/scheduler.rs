@@ -0,0 +1,555 @@
+use crate::shared::clock::{SimulationClock, SimulationTime};
+use crate::shared::connection::GameConnection;
+use crate::shared::fps::{FpsTracker, FramePacer};
+use crate::shared::logging;
+use crate::shared::metrics::{WorkerMetrics, DURATION_BUCKETS};
+#[cfg(feature = "prometheus")]
+use crate::shared::prometheus::MetricsEndpoint;
+use crate::shared::shutdown;
+use crate::shared::utils::duration_secs;
+use log::{error, info};
+use rand::rngs::StdRng;
+use spatialos_sdk::worker::view::View;
+use std::any::{Any, TypeId};
+use std::error::Error;
+use std::fmt;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to keep the connection open after the final responses have been sent, so that they
+/// make it onto the network before the process exits.
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(500);
+
+/// How often systems are asked for their metrics and the result is sent to SpatialOS.
+const METRICS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many of the most recent frames the frame rate and frame time percentiles cover.
+const TRACKED_FRAMES: usize = 600;
+
+/// A unit of behaviour that runs once per frame against the worker's view.
+pub trait System<C: GameConnection>: AsAny {
+    /// Unique name used to declare dependencies on this system.
+    fn name(&self) -> &'static str;
+
+    /// The systems that must run before this one. Only these can be read through
+    /// `Context::dependencies`.
+    fn dependencies(&self) -> Vec<Dependency> {
+        Vec::new()
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>);
+
+    /// Runs once when the worker is stopped by a signal, after the last ops have been applied to
+    /// the view. Systems that answer commands should answer any outstanding requests here.
+    fn shutdown(&mut self, _ctx: &mut Context<C>) {}
+
+    /// Records the system's gauges and counters. Called about once a second, after a tick.
+    fn report(&self, _view: &View, _metrics: &mut WorkerMetrics) {}
+}
+
+/// Lets the scheduler hand out systems as their concrete types.
+pub trait AsAny: Any {
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A system that must run before the one declaring it, and the type it is read back as.
+#[derive(Debug, Clone, Copy)]
+pub struct Dependency {
+    pub name: &'static str,
+    type_id: fn() -> TypeId,
+}
+
+impl Dependency {
+    pub fn on<T: Any>(name: &'static str) -> Self {
+        Dependency {
+            name,
+            type_id: TypeId::of::<T>,
+        }
+    }
+}
+
+/// Everything a system gets to work with during its tick.
+pub struct Context<'a, C: GameConnection> {
+    pub view: &'a View,
+    pub connection: &'a mut C,
+    pub rng: &'a mut StdRng,
+    pub time: SimulationTime,
+    pub dependencies: Dependencies<'a, C>,
+}
+
+/// Read-only access to the systems the ticking system declared as dependencies.
+pub struct Dependencies<'a, C: GameConnection> {
+    slots: &'a [Slot<C>],
+    allowed: &'a [Dependency],
+}
+
+impl<'a, C: GameConnection + 'static> Dependencies<'a, C> {
+    /// Finds a declared dependency by type. `Scheduler::add_system` has already checked that
+    /// every declared dependency is registered with the declared type.
+    ///
+    /// Panics if the ticking system did not declare a dependency of this type at all, which
+    /// `System::dependencies` right next to its tick makes plain.
+    pub fn get<T: Any>(&self) -> &'a T {
+        let dependency = self
+            .allowed
+            .iter()
+            .find(|dependency| (dependency.type_id)() == TypeId::of::<T>())
+            .unwrap_or_else(|| {
+                let names = self.allowed.iter().map(|dependency| dependency.name).collect::<Vec<_>>();
+                panic!("None of the dependencies {:?} has the requested type", names)
+            });
+
+        self.slots
+            .iter()
+            .find(|slot| slot.timing.name == dependency.name)
+            .and_then(|slot| slot.system.as_ref())
+            .and_then(|system| {
+                let system: &dyn System<C> = &**system;
+                system.as_any().downcast_ref::<T>()
+            })
+            .expect("Checked when the system was registered")
+    }
+}
+
+/// How long a system has spent in its tick.
+#[derive(Debug, Clone)]
+pub struct SystemTiming {
+    pub name: &'static str,
+    pub last: Duration,
+    pub total: Duration,
+    pub ticks: u64,
+}
+
+impl SystemTiming {
+    pub fn average(&self) -> Duration {
+        if self.ticks == 0 {
+            return Duration::from_secs(0);
+        }
+
+        self.total / self.ticks as u32
+    }
+}
+
+/// Why a worker's main loop stopped.
+#[derive(Debug)]
+pub enum StopReason {
+    Disconnected(String),
+    Interrupted,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Disconnected(reason) => write!(f, "Disconnected: {}", reason),
+            StopReason::Interrupted => write!(f, "Interrupted"),
+        }
+    }
+}
+
+impl Error for StopReason {}
+
+struct Slot<C: GameConnection> {
+    // Only `None` while the system itself is ticking.
+    system: Option<Box<dyn System<C>>>,
+    dependencies: Vec<Dependency>,
+    timing: SystemTiming,
+}
+
+/// Owns a worker's connection and view and runs its systems in registration order.
+pub struct Scheduler<C: GameConnection> {
+    connection: C,
+    view: View,
+    slots: Vec<Slot<C>>,
+    fps_tracker: FpsTracker,
+    pacer: FramePacer,
+    metrics: WorkerMetrics,
+    last_report: Instant,
+    #[cfg(feature = "prometheus")]
+    metrics_endpoint: Option<MetricsEndpoint>,
+}
+
+impl<C: GameConnection + 'static> Scheduler<C> {
+    /// Without a `target_fps` frames run back to back.
+    pub fn new(connection: C, target_fps: Option<f64>) -> Self {
+        Scheduler {
+            connection,
+            view: View::new(),
+            slots: Vec::new(),
+            fps_tracker: FpsTracker::new(TRACKED_FRAMES),
+            pacer: FramePacer::new(target_fps),
+            metrics: WorkerMetrics::new(),
+            last_report: Instant::now(),
+            #[cfg(feature = "prometheus")]
+            metrics_endpoint: None,
+        }
+    }
+
+    /// Registers a system to run after all previously registered ones. Its dependencies must
+    /// already be registered, with the types it declared them as.
+    pub fn add_system(&mut self, system: impl System<C> + 'static) -> Result<(), String> {
+        let name = system.name();
+
+        if self.slots.iter().any(|slot| slot.timing.name == name) {
+            return Err(format!("A system called \"{}\" is already registered", name));
+        }
+
+        let dependencies = system.dependencies();
+
+        for dependency in &dependencies {
+            let registered = match self.slots.iter().find(|slot| slot.timing.name == dependency.name) {
+                Some(slot) => slot.system.as_ref().expect("Systems only leave their slot while ticking"),
+                None => {
+                    return Err(format!(
+                        "System \"{}\" depends on \"{}\", which must be registered first",
+                        name, dependency.name
+                    ));
+                }
+            };
+
+            let registered: &dyn System<C> = &**registered;
+            if registered.as_any().type_id() != (dependency.type_id)() {
+                return Err(format!(
+                    "System \"{}\" depends on \"{}\", which is registered as a different type",
+                    name, dependency.name
+                ));
+            }
+        }
+
+        self.slots.push(Slot {
+            system: Some(Box::new(system)),
+            dependencies,
+            timing: SystemTiming {
+                name,
+                last: Duration::from_secs(0),
+                total: Duration::from_secs(0),
+                ticks: 0,
+            },
+        });
+
+        Ok(())
+    }
+
+    pub fn connection(&mut self) -> &mut C {
+        &mut self.connection
+    }
+
+    pub fn timings(&self) -> impl Iterator<Item = &SystemTiming> {
+        self.slots.iter().map(|slot| &slot.timing)
+    }
+
+    pub fn metrics(&self) -> &WorkerMetrics {
+        &self.metrics
+    }
+
+    /// Also publishes metrics to this endpoint whenever they are reported.
+    #[cfg(feature = "prometheus")]
+    pub fn publish_metrics_to(&mut self, endpoint: Option<MetricsEndpoint>) {
+        self.metrics_endpoint = endpoint;
+    }
+
+    /// Applies the latest ops to the view and ticks every system once.
+    pub fn tick(&mut self, rng: &mut StdRng, time: &SimulationTime) -> Result<(), StopReason> {
+        self.update_view()?;
+        self.run_systems(rng, time, |system, ctx| system.tick(ctx));
+        self.forward_logs();
+        Ok(())
+    }
+
+    /// Applies the last ops to the view, lets every system answer outstanding requests and
+    /// gives the connection a moment to send them.
+    pub fn shutdown(&mut self, rng: &mut StdRng, time: &SimulationTime) -> Result<(), StopReason> {
+        self.update_view()?;
+        self.run_systems(rng, time, |system, ctx| system.shutdown(ctx));
+        self.forward_logs();
+        thread::sleep(SHUTDOWN_GRACE);
+        Ok(())
+    }
+
+    /// Reports metrics if they are due, sleeps off whatever is left of the frame and records
+    /// how long it took.
+    pub fn end_frame(&mut self) {
+        if self.last_report.elapsed() >= METRICS_INTERVAL {
+            self.report_metrics();
+            self.last_report = Instant::now();
+        }
+
+        self.pacer.wait();
+        self.fps_tracker.tick();
+    }
+
+    /// Runs the worker's main loop until the connection drops or the process is interrupted.
+    pub fn run(&mut self, rng: &mut StdRng, clock: &mut SimulationClock) -> StopReason {
+        loop {
+            let time = clock.advance();
+
+            if shutdown::interrupted() {
+                info!("Interrupted, shutting down");
+
+                return match self.shutdown(rng, &time) {
+                    Ok(()) => StopReason::Interrupted,
+                    Err(reason) => reason,
+                };
+            }
+
+            if let Err(reason) = self.tick(rng, &time) {
+                error!("{}", reason);
+                return reason;
+            }
+
+            self.end_frame();
+        }
+    }
+
+    fn update_view(&mut self) -> Result<(), StopReason> {
+        self.view.clear_transient_data();
+        self.connection
+            .update_view(&mut self.view)
+            .map_err(StopReason::Disconnected)
+    }
+
+    fn report_metrics(&mut self) {
+        self.fps_tracker.report(&mut self.metrics);
+
+        for slot in &self.slots {
+            if let Some(ref system) = slot.system {
+                system.report(&self.view, &mut self.metrics);
+            }
+        }
+
+        #[cfg(feature = "prometheus")]
+        {
+            if let Some(ref endpoint) = self.metrics_endpoint {
+                endpoint.publish(&self.metrics);
+            }
+        }
+
+        let metrics = self.metrics.to_spatialos();
+        self.connection.send_metrics(&metrics);
+    }
+
+    fn forward_logs(&mut self) {
+        for log in logging::take_forwarded() {
+            self.connection.send_log_message(
+                log.spatialos_level(),
+                &log.target,
+                &log.message,
+                log.entity_id,
+            );
+        }
+    }
+
+    fn run_systems<F>(&mut self, rng: &mut StdRng, time: &SimulationTime, mut run: F)
+    where
+        F: FnMut(&mut dyn System<C>, &mut Context<C>),
+    {
+        logging::set_tick(time.tick);
+
+        for i in 0..self.slots.len() {
+            // Take the system out of its slot so that it can read the others while it ticks.
+            let mut system = self.slots[i].system.take().expect("System is already ticking");
+            let start = Instant::now();
+
+            {
+                let mut ctx = Context {
+                    view: &self.view,
+                    connection: &mut self.connection,
+                    rng: &mut *rng,
+                    time: *time,
+                    dependencies: Dependencies {
+                        slots: &self.slots,
+                        allowed: &self.slots[i].dependencies,
+                    },
+                };
+
+                logging::set_behaviour(Some(self.slots[i].timing.name));
+                run(&mut *system, &mut ctx);
+                logging::set_behaviour(None);
+            }
+
+            let slot = &mut self.slots[i];
+            slot.timing.last = start.elapsed();
+            self.metrics.observe(
+                "system_tick_seconds",
+                &[("system", slot.timing.name)],
+                DURATION_BUCKETS,
+                duration_secs(slot.timing.last),
+            );
+            slot.timing.total += slot.timing.last;
+            slot.timing.ticks += 1;
+            slot.system = Some(system);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shared::local::{LocalConnection, LocalRuntime};
+    use crate::shared::GAMELOGIC_LAYER;
+    use rand::SeedableRng;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    /// Logs its name whenever it ticks, and what its dependency has counted so far.
+    struct Recorder {
+        name: &'static str,
+        dependencies: Vec<Dependency>,
+        log: Log,
+        ticks: u32,
+        seen: Vec<u32>,
+    }
+
+    impl Recorder {
+        fn new(name: &'static str, log: &Log) -> Self {
+            Recorder {
+                name,
+                dependencies: Vec::new(),
+                log: log.clone(),
+                ticks: 0,
+                seen: Vec::new(),
+            }
+        }
+
+        fn after(mut self, dependency: Dependency) -> Self {
+            self.dependencies.push(dependency);
+            self
+        }
+    }
+
+    impl<C: GameConnection + 'static> System<C> for Recorder {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn dependencies(&self) -> Vec<Dependency> {
+            self.dependencies.clone()
+        }
+
+        fn tick(&mut self, ctx: &mut Context<C>) {
+            self.log.borrow_mut().push(self.name);
+            self.ticks += 1;
+
+            if !self.dependencies.is_empty() {
+                self.seen.push(ctx.dependencies.get::<Recorder>().ticks);
+            }
+        }
+    }
+
+    struct Other;
+
+    impl<C: GameConnection + 'static> System<C> for Other {
+        fn name(&self) -> &'static str {
+            "other"
+        }
+
+        fn tick(&mut self, ctx: &mut Context<C>) {
+            let _ = ctx.dependencies.get::<Recorder>();
+        }
+    }
+
+    fn scheduler() -> Scheduler<LocalConnection> {
+        let runtime = LocalRuntime::new();
+        Scheduler::new(runtime.connect("worker", GAMELOGIC_LAYER), None)
+    }
+
+    fn tick(scheduler: &mut Scheduler<LocalConnection>, frames: u32) {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut clock = SimulationClock::new(60.0);
+
+        for _ in 0..frames {
+            scheduler.tick(&mut rng, &clock.step()).unwrap();
+        }
+    }
+
+    #[test]
+    fn rejects_duplicate_names() {
+        let log = Log::default();
+        let mut scheduler = scheduler();
+        scheduler.add_system(Recorder::new("a", &log)).unwrap();
+
+        let error = scheduler.add_system(Recorder::new("a", &log)).unwrap_err();
+        assert_eq!(error, "A system called \"a\" is already registered");
+    }
+
+    #[test]
+    fn rejects_missing_dependencies() {
+        let log = Log::default();
+        let mut scheduler = scheduler();
+        scheduler.add_system(Recorder::new("a", &log)).unwrap();
+
+        let error = scheduler
+            .add_system(Recorder::new("b", &log).after(Dependency::on::<Recorder>("c")))
+            .unwrap_err();
+        assert_eq!(error, "System \"b\" depends on \"c\", which must be registered first");
+    }
+
+    #[test]
+    fn dependencies_must_be_registered_first() {
+        let log = Log::default();
+        let mut scheduler = scheduler();
+        let b = || Recorder::new("b", &log).after(Dependency::on::<Recorder>("a"));
+
+        assert!(scheduler.add_system(b()).is_err());
+        scheduler.add_system(Recorder::new("a", &log)).unwrap();
+        scheduler.add_system(b()).unwrap();
+    }
+
+    #[test]
+    fn rejects_dependencies_of_another_type() {
+        let log = Log::default();
+        let mut scheduler = scheduler();
+        scheduler.add_system(Other).unwrap();
+
+        let error = scheduler
+            .add_system(Recorder::new("b", &log).after(Dependency::on::<Recorder>("other")))
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "System \"b\" depends on \"other\", which is registered as a different type"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "None of the dependencies [] has the requested type")]
+    fn panics_when_reading_an_undeclared_dependency() {
+        let log = Log::default();
+        let mut scheduler = scheduler();
+        scheduler.add_system(Recorder::new("a", &log)).unwrap();
+        scheduler.add_system(Other).unwrap();
+
+        tick(&mut scheduler, 1);
+    }
+
+    #[test]
+    fn ticks_in_registration_order_after_dependencies() {
+        let log = Log::default();
+        let mut scheduler = scheduler();
+        scheduler.add_system(Recorder::new("a", &log)).unwrap();
+        scheduler
+            .add_system(Recorder::new("b", &log).after(Dependency::on::<Recorder>("a")))
+            .unwrap();
+        scheduler.add_system(Recorder::new("c", &log)).unwrap();
+
+        tick(&mut scheduler, 2);
+
+        assert_eq!(*log.borrow(), vec!["a", "b", "c", "a", "b", "c"]);
+
+        let b = scheduler.slots[1].system.as_ref().unwrap();
+        let b: &dyn System<LocalConnection> = &**b;
+        assert_eq!(b.as_any().downcast_ref::<Recorder>().unwrap().seen, vec![1, 2]);
+
+        let timings = scheduler
+            .timings()
+            .map(|timing| (timing.name, timing.ticks))
+            .collect::<Vec<_>>();
+        assert_eq!(timings, vec![("a", 2), ("b", 2), ("c", 2)]);
+    }
+}
@@ -0,0 +1,122 @@
+use crate::shared::connection::GameConnection;
+use crate::shared::generated::demo::Headquarters;
+use crate::shared::metrics::WorkerMetrics;
+use crate::shared::scheduler::{Context, System};
+use log::debug;
+use spatialos_sdk::worker::view::{View, ViewQuery};
+use spatialos_sdk::worker::EntityId;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Each team's score: the total score of its HQs in the view.
+#[derive(Default)]
+pub struct Scoreboard {
+    scores: BTreeMap<u32, u32>,
+}
+
+impl Scoreboard {
+    pub fn new() -> Self {
+        Scoreboard {
+            scores: BTreeMap::new(),
+        }
+    }
+
+    pub fn tick(&mut self, view: &View) {
+        let mut scores = BTreeMap::new();
+
+        for hq in view.query::<HqQuery>() {
+            let score = scores.entry(hq.hq.team).or_insert(0u32);
+            *score = score.saturating_add(hq.hq.score);
+        }
+
+        if scores != self.scores {
+            self.scores = scores;
+            debug!("Scores: {}", self);
+        }
+    }
+
+    /// Scores by team.
+    pub fn scores(&self) -> &BTreeMap<u32, u32> {
+        &self.scores
+    }
+
+    /// The team ahead of all others and its score. `None` while no team is ahead, e.g. when
+    /// the top teams are tied.
+    pub fn leader(&self) -> Option<(u32, u32)> {
+        let mut leader = None;
+        let mut tied = false;
+
+        for (team, score) in &self.scores {
+            match leader {
+                Some((_, best)) if *score < best => {}
+                Some((_, best)) if *score == best => tied = true,
+                _ => {
+                    leader = Some((*team, *score));
+                    tied = false;
+                }
+            }
+        }
+
+        if tied {
+            None
+        } else {
+            leader
+        }
+    }
+
+    pub fn report(&self, metrics: &mut WorkerMetrics) {
+        // Teams can leave the view along with their HQs.
+        metrics.clear_gauges("team_score");
+
+        for (team, score) in &self.scores {
+            let team = team.to_string();
+            metrics.set_gauge("team_score", &[("team", team.as_str())], f64::from(*score));
+        }
+    }
+}
+
+impl fmt::Display for Scoreboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.scores.is_empty() {
+            return write!(f, "no teams");
+        }
+
+        let teams = self
+            .scores
+            .iter()
+            .map(|(team, score)| format!("team {} {}", team, score))
+            .collect::<Vec<String>>();
+
+        write!(f, "{}", teams.join(", "))
+    }
+}
+
+impl<C: GameConnection + 'static> System<C> for Scoreboard {
+    fn name(&self) -> &'static str {
+        "scoreboard"
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        Scoreboard::tick(self, ctx.view);
+    }
+
+    fn report(&self, _view: &View, metrics: &mut WorkerMetrics) {
+        Scoreboard::report(self, metrics);
+    }
+}
+
+struct HqQuery<'a> {
+    hq: &'a Headquarters,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for HqQuery<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<Headquarters>(entity_id).is_some()
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        HqQuery {
+            hq: view.get_component::<Headquarters>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
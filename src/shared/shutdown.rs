@@ -0,0 +1,22 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Exit code used when a second Ctrl-C cuts a graceful shutdown short.
+const FORCED_EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a Ctrl-C / SIGTERM handler. The first signal asks the main loop to stop at the end of
+/// the current frame; a second one exits immediately.
+pub fn listen_for_interrupt() -> Result<(), String> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            process::exit(FORCED_EXIT_CODE);
+        }
+    })
+    .map_err(|e| format!("Could not install the interrupt handler: {}", e))
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,54 @@
+use crate::shared::generated::demo::{Fire, Headquarters, Lumberjack, MatchState, Tree, Wizard};
+use crate::shared::generated::improbable::{EntityAcl, Metadata, Persistence, Position};
+use spatialos_sdk::worker::component::{Component, ComponentId};
+use spatialos_sdk::worker::entity::Entity;
+use spatialos_sdk::worker::snapshot::SnapshotInputStream;
+use spatialos_sdk::worker::EntityId;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Reads every entity in a snapshot, ordered by entity id.
+pub fn read_snapshot<P: AsRef<Path>>(path: P) -> Result<BTreeMap<EntityId, Entity>, String> {
+    let mut stream = SnapshotInputStream::new(path)?;
+    let mut entities = BTreeMap::new();
+
+    while stream.has_next() {
+        let (entity_id, entity) = stream.read_entity()?;
+        entities.insert(entity_id, entity);
+    }
+
+    Ok(entities)
+}
+
+type ComponentProbe = fn(&Entity) -> Option<ComponentId>;
+
+/// Every component this project uses, by name.
+const COMPONENTS: [(&str, ComponentProbe); 10] = [
+    ("Position", probe::<Position>),
+    ("Metadata", probe::<Metadata>),
+    ("Persistence", probe::<Persistence>),
+    ("EntityAcl", probe::<EntityAcl>),
+    ("Tree", probe::<Tree>),
+    ("Lumberjack", probe::<Lumberjack>),
+    ("Headquarters", probe::<Headquarters>),
+    ("Fire", probe::<Fire>),
+    ("Wizard", probe::<Wizard>),
+    ("MatchState", probe::<MatchState>),
+];
+
+/// The ids and names of the components on an entity, out of the components this project uses.
+pub fn components_of(entity: &Entity) -> Vec<(ComponentId, &'static str)> {
+    COMPONENTS
+        .iter()
+        .filter_map(|(name, probe)| probe(entity).map(|id| (id, *name)))
+        .collect()
+}
+
+/// The names `components_of` can return.
+pub fn component_names() -> Vec<&'static str> {
+    COMPONENTS.iter().map(|(name, _)| *name).collect()
+}
+
+fn probe<C: Component>(entity: &Entity) -> Option<ComponentId> {
+    entity.get::<C>().map(|_| C::ID)
+}
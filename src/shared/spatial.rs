@@ -0,0 +1,259 @@
+use crate::shared::generated::improbable::Coordinates;
+use kdtree::distance::squared_euclidean;
+use kdtree::KdTree;
+use log::warn;
+use spatialos_sdk::worker::EntityId;
+use std::collections::BTreeMap;
+
+/// Below this many stale entries the k-d tree is never rebuilt.
+const MIN_STALE_FOR_REBUILD: usize = 64;
+
+/// A set of entity positions that supports radius and nearest-neighbour queries.
+///
+/// `KdTree` cannot remove points, so removing or moving an entity only marks its old entry as
+/// stale. Queries skip stale entries and the tree is rebuilt once they outnumber the live ones.
+pub struct SpatialIndex {
+    tree: KdTree<IndexEntry, [f64; 3]>,
+    positions: BTreeMap<EntityId, (Coordinates, u64)>,
+    next_generation: u64,
+    stale: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct IndexEntry {
+    entity_id: EntityId,
+    generation: u64,
+}
+
+impl SpatialIndex {
+    pub fn new() -> Self {
+        SpatialIndex {
+            tree: KdTree::new(3),
+            positions: BTreeMap::new(),
+            next_generation: 0,
+            stale: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn contains(&self, entity_id: EntityId) -> bool {
+        self.positions.contains_key(&entity_id)
+    }
+
+    pub fn get(&self, entity_id: EntityId) -> Option<&Coordinates> {
+        self.positions.get(&entity_id).map(|(coords, _)| coords)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (EntityId, &Coordinates)> {
+        self.positions
+            .iter()
+            .map(|(entity_id, (coords, _))| (*entity_id, coords))
+    }
+
+    /// Adds an entity, or moves it if it is already in the index.
+    pub fn insert(&mut self, entity_id: EntityId, coords: Coordinates) {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+
+        let entry = IndexEntry {
+            entity_id,
+            generation,
+        };
+
+        if let Err(e) = self.tree.add(to_point(&coords), entry) {
+            warn!("Could not index {:?} at {:?}: {:?}", entity_id, coords, e);
+            return;
+        }
+
+        if self.positions.insert(entity_id, (coords, generation)).is_some() {
+            self.stale += 1;
+            self.rebuild_if_stale();
+        }
+    }
+
+    pub fn remove(&mut self, entity_id: EntityId) -> Option<Coordinates> {
+        let (coords, _) = self.positions.remove(&entity_id)?;
+        self.stale += 1;
+        self.rebuild_if_stale();
+
+        Some(coords)
+    }
+
+    /// All entities within `radius` of `coords`, nearest first.
+    pub fn within(&self, coords: &Coordinates, radius: f64) -> Vec<EntityId> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        match self
+            .tree
+            .within(&to_point(coords), radius.powi(2), &squared_euclidean)
+        {
+            Ok(found) => found
+                .into_iter()
+                .filter(|(_, entry)| self.is_live(entry))
+                .map(|(_, entry)| entry.entity_id)
+                .collect(),
+            Err(e) => {
+                warn!("Invalid spatial query around {:?}: {:?}", coords, e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Up to `count` entities, nearest to `coords` first.
+    pub fn nearest(&self, coords: &Coordinates, count: usize) -> Vec<EntityId> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let point = to_point(coords);
+
+        match self.tree.iter_nearest(&point, &squared_euclidean) {
+            Ok(found) => found
+                .filter(|(_, entry)| self.is_live(entry))
+                .take(count)
+                .map(|(_, entry)| entry.entity_id)
+                .collect(),
+            Err(e) => {
+                warn!("Invalid spatial query around {:?}: {:?}", coords, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn is_live(&self, entry: &IndexEntry) -> bool {
+        match self.positions.get(&entry.entity_id) {
+            Some((_, generation)) => *generation == entry.generation,
+            None => false,
+        }
+    }
+
+    fn rebuild_if_stale(&mut self) {
+        if self.stale > MIN_STALE_FOR_REBUILD && self.stale > self.positions.len() {
+            self.rebuild();
+        }
+    }
+
+    fn rebuild(&mut self) {
+        let mut tree = KdTree::new(3);
+
+        for (entity_id, (coords, generation)) in &self.positions {
+            let entry = IndexEntry {
+                entity_id: *entity_id,
+                generation: *generation,
+            };
+
+            // Every position here was accepted by `add` once already.
+            let _ = tree.add(to_point(coords), entry);
+        }
+
+        self.tree = tree;
+        self.stale = 0;
+    }
+}
+
+fn to_point(coords: &Coordinates) -> [f64; 3] {
+    [coords.x, coords.y, coords.z]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coords(x: f64, z: f64) -> Coordinates {
+        Coordinates { x, y: 0.0, z }
+    }
+
+    fn id(id: i64) -> EntityId {
+        EntityId::new(id)
+    }
+
+    fn line(count: i64) -> SpatialIndex {
+        let mut index = SpatialIndex::new();
+        for i in 0..count {
+            index.insert(id(i), coords(i as f64, 0.0));
+        }
+        index
+    }
+
+    #[test]
+    fn queries_on_an_empty_index_find_nothing() {
+        let index = SpatialIndex::new();
+
+        assert!(index.within(&coords(0.0, 0.0), 10.0).is_empty());
+        assert!(index.nearest(&coords(0.0, 0.0), 3).is_empty());
+    }
+
+    #[test]
+    fn within_finds_entities_in_radius_nearest_first() {
+        let index = line(10);
+
+        assert_eq!(index.within(&coords(4.2, 0.0), 1.5), vec![id(4), id(5), id(3)]);
+        assert!(index.within(&coords(0.0, 50.0), 5.0).is_empty());
+    }
+
+    #[test]
+    fn nearest_returns_up_to_count_entities() {
+        let index = line(5);
+
+        assert_eq!(index.nearest(&coords(0.9, 0.0), 2), vec![id(1), id(0)]);
+        assert_eq!(index.nearest(&coords(0.0, 0.0), 10).len(), 5);
+    }
+
+    #[test]
+    fn reinserting_moves_the_entity() {
+        let mut index = line(3);
+        index.insert(id(0), coords(100.0, 0.0));
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.get(id(0)).map(|c| c.x), Some(100.0));
+        assert_eq!(index.within(&coords(0.0, 0.0), 1.5), vec![id(1)]);
+        assert_eq!(index.nearest(&coords(0.0, 0.0), 3), vec![id(1), id(2), id(0)]);
+    }
+
+    #[test]
+    fn removed_entities_are_not_found() {
+        let mut index = line(3);
+
+        assert_eq!(index.remove(id(1)).map(|c| c.x), Some(1.0));
+        assert!(index.remove(id(1)).is_none());
+        assert!(!index.contains(id(1)));
+        assert_eq!(index.within(&coords(1.2, 0.0), 1.5), vec![id(2), id(0)]);
+        assert_eq!(index.nearest(&coords(1.0, 0.0), 3).len(), 2);
+    }
+
+    #[test]
+    fn removing_most_entities_rebuilds_the_tree() {
+        let count = MIN_STALE_FOR_REBUILD as i64 * 2;
+        let mut index = line(count);
+
+        for i in 0..=MIN_STALE_FOR_REBUILD as i64 {
+            index.remove(id(i));
+        }
+
+        assert_eq!(index.stale, 0);
+        assert_eq!(index.tree.size(), index.len());
+        assert_eq!(index.nearest(&coords(0.0, 0.0), 1), vec![id(MIN_STALE_FOR_REBUILD as i64 + 1)]);
+    }
+
+    #[test]
+    fn moving_entities_repeatedly_rebuilds_the_tree() {
+        let mut index = line(1);
+
+        for i in 0..=MIN_STALE_FOR_REBUILD {
+            index.insert(id(0), coords(i as f64, 0.0));
+        }
+
+        assert_eq!(index.stale, 0);
+        assert_eq!(index.tree.size(), 1);
+        assert_eq!(index.within(&coords(0.0, 0.0), 1000.0), vec![id(0)]);
+    }
+}
@@ -1,12 +1,10 @@
-use crate::shared::generated::demo::{Action, ActionType, Headquarters, Lumberjack, Tree, Fire, Wizard, WizardFaction, WizardAction, WizardActionType};
+use crate::shared::generated::demo::{Action, ActionType, Headquarters, Lumberjack, MatchPhase, MatchState, Tree, Fire, Wizard, WizardFaction, WizardAction, WizardActionType};
 use crate::shared::generated::improbable::Vector3d;
 use crate::shared::{CLIENT_LAYER, GAMELOGIC_LAYER};
 use spatialos_sdk::worker::entity::Entity;
 use spatialos_sdk::worker::entity_builder::EntityBuilder;
 
-const TREE_RESOURCE_COUNT: u32 = 5;
-
-pub fn tree(position: &Vector3d) -> Result<Entity, String> {
+pub fn tree(position: &Vector3d, resources: u32) -> Result<Entity, String> {
     let mut builder = EntityBuilder::new(position.x, position.y, position.z, GAMELOGIC_LAYER);
     builder.set_metadata("Tree", GAMELOGIC_LAYER);
     builder.set_persistent(GAMELOGIC_LAYER);
@@ -15,7 +13,8 @@ pub fn tree(position: &Vector3d) -> Result<Entity, String> {
 
     builder.add_component(
         Tree {
-            resources_left: TREE_RESOURCE_COUNT,
+            resources_left: resources,
+            max_resources: resources,
         },
         GAMELOGIC_LAYER,
     );
@@ -29,7 +28,7 @@ pub fn tree(position: &Vector3d) -> Result<Entity, String> {
     builder.build()
 }
 
-pub fn lumberjack(position: &Vector3d) -> Result<Entity, String> {
+pub fn lumberjack(position: &Vector3d, team: u32) -> Result<Entity, String> {
     let mut builder = EntityBuilder::new(position.x, position.y, position.z, GAMELOGIC_LAYER);
     builder.set_metadata("Lumberjack", GAMELOGIC_LAYER);
     builder.set_persistent(GAMELOGIC_LAYER);
@@ -42,6 +41,9 @@ pub fn lumberjack(position: &Vector3d) -> Result<Entity, String> {
                 typ: ActionType::IDLE,
                 target: None,
             },
+            pending_chop: None,
+            carrying: 0,
+            team,
         },
         GAMELOGIC_LAYER,
     );
@@ -49,14 +51,40 @@ pub fn lumberjack(position: &Vector3d) -> Result<Entity, String> {
     builder.build()
 }
 
-pub fn headquarters(position: &Vector3d) -> Result<Entity, String> {
+/// How far from their HQ lumberjacks start when a world does not say otherwise.
+pub const DEFAULT_LUMBERJACK_RADIUS: u32 = 15;
+
+pub fn headquarters(position: &Vector3d, team: u32, lumberjack_radius: u32) -> Result<Entity, String> {
     let mut builder = EntityBuilder::new(position.x, position.y, position.z, GAMELOGIC_LAYER);
     builder.set_metadata("Headquarters", GAMELOGIC_LAYER);
     builder.set_persistent(GAMELOGIC_LAYER);
     builder.set_entity_acl_write_access(GAMELOGIC_LAYER);
     builder.add_read_access(CLIENT_LAYER);
 
-    builder.add_component(Headquarters { score: 0 }, GAMELOGIC_LAYER);
+    builder.add_component(Headquarters { score: 0, team, lumberjack_radius }, GAMELOGIC_LAYER);
+
+    builder.build()
+}
+
+/// The single entity that holds the state of the match. Starts the first round.
+pub fn match_state() -> Result<Entity, String> {
+    let mut builder = EntityBuilder::new(0.0, 0.0, 0.0, GAMELOGIC_LAYER);
+    builder.set_metadata("Match", GAMELOGIC_LAYER);
+    builder.set_persistent(GAMELOGIC_LAYER);
+    builder.set_entity_acl_write_access(GAMELOGIC_LAYER);
+    builder.add_read_access(CLIENT_LAYER);
+
+    builder.add_component(
+        MatchState {
+            round: 1,
+            phase: MatchPhase::PLAYING,
+            phase_elapsed: 0.0,
+            last_result: None,
+            score_target: 0,
+            time_limit_secs: 0,
+        },
+        GAMELOGIC_LAYER,
+    );
 
     builder.build()
 }
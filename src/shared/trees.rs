@@ -0,0 +1,164 @@
+use crate::shared::connection::GameConnection;
+use crate::shared::generated::demo::{Fire, Tree};
+use crate::shared::generated::improbable::{Coordinates, Position};
+use crate::shared::metrics::WorkerMetrics;
+use crate::shared::scheduler::{Context, System};
+use crate::shared::spatial::SpatialIndex;
+use spatialos_sdk::worker::view::{View, ViewQuery};
+use spatialos_sdk::worker::EntityId;
+
+/// Decides whether a tree can currently be chopped by lumberjacks or set on fire by wizards.
+pub type Availability = fn(&Tree, &Fire) -> bool;
+
+/// The availability rule every worker uses: a tree is available while it has resources left and
+/// is not on fire.
+pub fn choppable(tree: &Tree, fire: &Fire) -> bool {
+    tree.resources_left > 0 && !fire.is_on_fire
+}
+
+/// Tracks the positions of all trees in the view, split into available and unavailable trees.
+pub struct TreeTracker {
+    available: SpatialIndex,
+    unavailable: SpatialIndex,
+    is_available: Availability,
+}
+
+impl TreeTracker {
+    pub fn new(is_available: Availability) -> Self {
+        TreeTracker {
+            available: SpatialIndex::new(),
+            unavailable: SpatialIndex::new(),
+            is_available,
+        }
+    }
+
+    /// Picks up trees that were added, removed or updated since the last tick.
+    pub fn tick(&mut self, view: &View) {
+        for removed in view.iter_entities_removed() {
+            self.available.remove(*removed);
+            self.unavailable.remove(*removed);
+        }
+
+        for changed in view.query::<TreeChangedQuery>() {
+            let available = (self.is_available)(changed.tree, changed.fire);
+            self.place(changed.entity_id, &changed.position.coords, available);
+        }
+    }
+
+    /// Re-evaluates a tree from component values that have been sent but not yet received back,
+    /// so that the rest of the tick already sees the change.
+    pub fn refresh(&mut self, entity_id: EntityId, tree: &Tree, fire: &Fire) {
+        let coords = match self
+            .available
+            .get(entity_id)
+            .or_else(|| self.unavailable.get(entity_id))
+        {
+            Some(coords) => coords.clone(),
+            None => return,
+        };
+
+        let available = (self.is_available)(tree, fire);
+        self.place(entity_id, &coords, available);
+    }
+
+    pub fn is_available(&self, entity_id: EntityId) -> bool {
+        self.available.contains(entity_id)
+    }
+
+    pub fn within_available(&self, coords: &Coordinates, radius: f64) -> Vec<EntityId> {
+        self.available.within(coords, radius)
+    }
+
+    pub fn within_unavailable(&self, coords: &Coordinates, radius: f64) -> Vec<EntityId> {
+        self.unavailable.within(coords, radius)
+    }
+
+    pub fn nearest_available(&self, coords: &Coordinates, count: usize) -> Vec<EntityId> {
+        self.available.nearest(coords, count)
+    }
+
+    pub fn available_count(&self) -> usize {
+        self.available.len()
+    }
+
+    pub fn unavailable_count(&self) -> usize {
+        self.unavailable.len()
+    }
+
+    /// Records how many trees are available, unavailable and on fire.
+    pub fn report(&self, view: &View, metrics: &mut WorkerMetrics) {
+        metrics.set_gauge("trees", &[("state", "available")], self.available_count() as f64);
+        metrics.set_gauge("trees", &[("state", "unavailable")], self.unavailable_count() as f64);
+        metrics.set_gauge("trees_burning", &[], view.query::<BurningTreeQuery>().count() as f64);
+    }
+
+    fn place(&mut self, entity_id: EntityId, coords: &Coordinates, available: bool) {
+        let (to, from) = if available {
+            (&mut self.available, &mut self.unavailable)
+        } else {
+            (&mut self.unavailable, &mut self.available)
+        };
+
+        from.remove(entity_id);
+
+        if !to.contains(entity_id) {
+            to.insert(entity_id, coords.clone());
+        }
+    }
+}
+
+impl<C: GameConnection + 'static> System<C> for TreeTracker {
+    fn name(&self) -> &'static str {
+        "trees"
+    }
+
+    fn tick(&mut self, ctx: &mut Context<C>) {
+        TreeTracker::tick(self, ctx.view);
+    }
+
+    fn report(&self, view: &View, metrics: &mut WorkerMetrics) {
+        TreeTracker::report(self, view, metrics);
+    }
+}
+
+struct TreeChangedQuery<'a> {
+    entity_id: EntityId,
+    position: &'a Position,
+    tree: &'a Tree,
+    fire: &'a Fire,
+}
+
+impl<'a, 'b: 'a> ViewQuery<'b> for TreeChangedQuery<'a> {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<Tree>(entity_id).is_some()
+            && view.get_component::<Fire>(entity_id).is_some()
+            && view.get_component::<Position>(entity_id).is_some()
+            && (view.was_entity_added(entity_id)
+                || view.was_component_updated::<Tree>(entity_id)
+                || view.was_component_updated::<Fire>(entity_id))
+    }
+
+    fn select(view: &'b View, entity_id: EntityId) -> Self {
+        TreeChangedQuery {
+            entity_id,
+            position: view.get_component::<Position>(entity_id).expect("Checked by filter"),
+            tree: view.get_component::<Tree>(entity_id).expect("Checked by filter"),
+            fire: view.get_component::<Fire>(entity_id).expect("Checked by filter"),
+        }
+    }
+}
+
+struct BurningTreeQuery;
+
+impl<'b> ViewQuery<'b> for BurningTreeQuery {
+    fn filter(view: &View, entity_id: EntityId) -> bool {
+        view.get_component::<Tree>(entity_id).is_some()
+            && view
+                .get_component::<Fire>(entity_id)
+                .map_or(false, |fire| fire.is_on_fire)
+    }
+
+    fn select(_view: &'b View, _entity_id: EntityId) -> Self {
+        BurningTreeQuery
+    }
+}
@@ -1,6 +1,8 @@
 use crate::shared::generated::improbable::{Coordinates, Vector3d};
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use log::info;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
 
 pub fn squared_distance(c1: &Coordinates, c2: &Coordinates) -> f64 {
     (c1.x - c2.x).powi(2) + (c1.y - c2.y).powi(2) + (c1.z - c2.z).powi(2)
@@ -44,14 +46,19 @@ pub fn add_coords(c1: &Coordinates, c2: &Coordinates) -> Coordinates {
     }
 }
 
-pub fn move_to(from: &Coordinates, to: &Coordinates, speed: f64) -> Coordinates {
+/// Moves from one position towards another by at most `distance`, without overshooting.
+pub fn move_to(from: &Coordinates, to: &Coordinates, distance: f64) -> Coordinates {
+    if squared_distance(from, to) <= distance.powi(2) {
+        return to.clone();
+    }
+
     let mut position_change = normalized_direction(to, from);
-    multiply(&mut position_change, speed);
+    multiply(&mut position_change, distance);
 
     add_coords(from, &position_change)
 }
 
-pub fn get_random_coords(center: &Vector3d, radius: i32, rng: &mut ThreadRng) -> Vector3d {
+pub fn get_random_coords<R: Rng>(center: &Vector3d, radius: i32, rng: &mut R) -> Vector3d {
     let mut position = center.clone();
 
     let angle_adjustment = rng.gen_range(0.0, 2.0 * std::f64::consts::PI);
@@ -62,3 +69,16 @@ pub fn get_random_coords(center: &Vector3d, radius: i32, rng: &mut ThreadRng) ->
 
     position
 }
+
+/// Creates the root random number generator for a run. When no seed is given a random one is
+/// picked and logged, so that the run can be reproduced by passing it back in with `--seed`.
+pub fn create_rng(seed: Option<u64>) -> StdRng {
+    let seed = seed.unwrap_or_else(rand::random);
+    info!("Using seed {}", seed);
+
+    StdRng::seed_from_u64(seed)
+}
+
+pub fn duration_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1_000_000_000.0
+}
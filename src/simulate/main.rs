@@ -0,0 +1,107 @@
+#[path = "../gamelogic/behaviors/mod.rs"]
+mod gamelogic;
+#[path = "../client/behaviors/mod.rs"]
+mod client;
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use rust_ldn_demo::shared::clock::SimulationClock;
+use rust_ldn_demo::shared::config::WorkerConfig;
+use rust_ldn_demo::shared::local::{LocalConnection, LocalRuntime};
+use rust_ldn_demo::shared::logging;
+use rust_ldn_demo::shared::scheduler::Scheduler;
+use rust_ldn_demo::shared::shutdown;
+use rust_ldn_demo::shared::snapshot::read_snapshot;
+use rust_ldn_demo::shared::utils::create_rng;
+use rust_ldn_demo::shared::{CLIENT_LAYER, GAMELOGIC_LAYER};
+use rand::rngs::StdRng;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::from_args();
+
+    let mut config = WorkerConfig::load_without_flags(opt.config.as_ref().map(PathBuf::as_path))?;
+    if let Some(ref log_level) = opt.log_level {
+        config.log_level = log_level.parse()?;
+    }
+    // There is no SpatialOS worker log to forward to.
+    config.log_to_spatialos = false;
+    logging::init(&config)?;
+
+    let runtime = LocalRuntime::new();
+    let mut rng = create_rng(opt.seed);
+
+    for (entity_id, entity) in read_snapshot(&opt.snapshot_path)? {
+        runtime.add_entity(entity_id, &entity);
+    }
+
+    let mut gamelogic = Scheduler::new(runtime.connect("RustWorker-local", GAMELOGIC_LAYER), config.frame_rate_cap());
+    gamelogic::add_systems(&mut gamelogic, &config)?;
+
+    let mut good_wizards = wizard_client(runtime.connect("GoodWizards", CLIENT_LAYER), false, &config, &mut rng)?;
+    let mut evil_wizards = wizard_client(runtime.connect("EvilWizards", CLIENT_LAYER), true, &config, &mut rng)?;
+
+    let mut clock = SimulationClock::new(60.0);
+    shutdown::listen_for_interrupt()?;
+
+    loop {
+        // Always step by exactly one fixed step, so that a seeded match plays out the same way
+        // however fast this machine is.
+        let time = clock.step();
+
+        if shutdown::interrupted() {
+            gamelogic.shutdown(&mut rng, &time)?;
+            good_wizards.shutdown(&mut rng, &time)?;
+            evil_wizards.shutdown(&mut rng, &time)?;
+            return Ok(());
+        }
+
+        gamelogic.tick(&mut rng, &time)?;
+        good_wizards.tick(&mut rng, &time)?;
+        evil_wizards.tick(&mut rng, &time)?;
+
+        // All three workers share one frame, so only one of them reports and paces it.
+        gamelogic.end_frame();
+    }
+}
+
+fn wizard_client(
+    mut connection: LocalConnection,
+    evil: bool,
+    config: &WorkerConfig,
+    rng: &mut StdRng,
+) -> Result<Scheduler<LocalConnection>, String> {
+    client::spawn_wizards(&mut connection, evil, rng)?;
+
+    let mut scheduler = Scheduler::new(connection, config.frame_rate_cap());
+    client::add_systems(&mut scheduler, config)?;
+
+    Ok(scheduler)
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    name = "simulate",
+    about = "Runs the gamelogic worker and both wizard clients against an in-process world."
+)]
+struct Opt {
+    #[structopt(
+        short = "p",
+        long = "snapshot-path",
+        default_value = "snapshots/default.snapshot"
+    )]
+    snapshot_path: PathBuf,
+
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Settings shared with the workers, as TOML or JSON. Falls back to `RUST_LDN_CONFIG`.
+    /// `RUST_LDN_*` environment variables override the file.
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// A level, optionally followed by per-module levels, e.g.
+    /// "info,simulate::gamelogic::trees=debug". Overrides the config.
+    #[structopt(long = "log-level")]
+    log_level: Option<String>,
+}
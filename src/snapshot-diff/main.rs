@@ -0,0 +1,375 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use rust_ldn_demo::shared::generated::demo::{Fire, Headquarters, Lumberjack, Tree, Wizard};
+use rust_ldn_demo::shared::generated::improbable::Position;
+use rust_ldn_demo::shared::snapshot::read_snapshot;
+use rust_ldn_demo::shared::utils::squared_distance;
+use spatialos_sdk::worker::component::{Component, ComponentId};
+use spatialos_sdk::worker::entity::Entity;
+use spatialos_sdk::worker::EntityId;
+
+const MOVE_THRESHOLD: f64 = 0.001;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::from_args();
+    let before = read_snapshot(&opt.before)?;
+    let after = read_snapshot(&opt.after)?;
+
+    let report = diff(&before, &after);
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
+
+fn diff(before: &BTreeMap<EntityId, Entity>, after: &BTreeMap<EntityId, Entity>) -> Report {
+    let mut report = Report {
+        added: after
+            .keys()
+            .filter(|id| !before.contains_key(id))
+            .map(|id| id.id)
+            .collect(),
+        removed: before
+            .keys()
+            .filter(|id| !after.contains_key(id))
+            .map(|id| id.id)
+            .collect(),
+        changed: BTreeMap::new(),
+        totals: Totals::default(),
+    };
+
+    for (entity_id, old) in before {
+        let new = match after.get(entity_id) {
+            Some(entity) => entity,
+            None => continue,
+        };
+
+        let changes = diff_entity(old, new);
+        if changes.is_empty() {
+            continue;
+        }
+
+        report.totals.count(&changes);
+        report.changed.insert(entity_id.id, changes);
+    }
+
+    report
+}
+
+fn diff_entity(old: &Entity, new: &Entity) -> BTreeMap<ComponentId, Change> {
+    let mut changes = BTreeMap::new();
+
+    if let Some((old, new)) = both::<Position>(old, new) {
+        let distance = squared_distance(&old.coords, &new.coords).sqrt();
+        if distance > MOVE_THRESHOLD {
+            changes.insert(
+                Position::ID,
+                Change::Moved {
+                    from: [old.coords.x, old.coords.y, old.coords.z],
+                    to: [new.coords.x, new.coords.y, new.coords.z],
+                    distance,
+                },
+            );
+        }
+    }
+
+    if let Some((old, new)) = both::<Tree>(old, new) {
+        if old.resources_left != new.resources_left {
+            changes.insert(
+                Tree::ID,
+                Change::Resources {
+                    before: old.resources_left,
+                    after: new.resources_left,
+                    depleted: old.resources_left > 0 && new.resources_left == 0,
+                },
+            );
+        }
+    }
+
+    if let Some((old, new)) = both::<Lumberjack>(old, new) {
+        if old.action.typ != new.action.typ {
+            changes.insert(
+                Lumberjack::ID,
+                Change::Action {
+                    before: format!("{:?}", old.action.typ),
+                    after: format!("{:?}", new.action.typ),
+                },
+            );
+        }
+    }
+
+    if let Some((old, new)) = both::<Headquarters>(old, new) {
+        if old.score != new.score {
+            changes.insert(
+                Headquarters::ID,
+                Change::Score {
+                    before: old.score,
+                    after: new.score,
+                    delta: i64::from(new.score) - i64::from(old.score),
+                },
+            );
+        }
+    }
+
+    if let Some((old, new)) = both::<Fire>(old, new) {
+        if old.is_on_fire != new.is_on_fire {
+            changes.insert(
+                Fire::ID,
+                Change::Fire {
+                    before: old.is_on_fire,
+                    after: new.is_on_fire,
+                },
+            );
+        }
+    }
+
+    if let Some((old, new)) = both::<Wizard>(old, new) {
+        if old.action.typ != new.action.typ {
+            changes.insert(
+                Wizard::ID,
+                Change::Action {
+                    before: format!("{:?}", old.action.typ),
+                    after: format!("{:?}", new.action.typ),
+                },
+            );
+        }
+    }
+
+    changes
+}
+
+fn both<'a, C: Component>(old: &'a Entity, new: &'a Entity) -> Option<(&'a C, &'a C)> {
+    match (old.get::<C>(), new.get::<C>()) {
+        (Some(old), Some(new)) => Some((old, new)),
+        _ => None,
+    }
+}
+
+fn print_report(report: &Report) {
+    let totals = &report.totals;
+
+    println!("Added entities: {:?}", report.added);
+    println!("Removed entities: {:?}", report.removed);
+    println!("Changed entities: {}", report.changed.len());
+    println!("  Trees depleted: {}", totals.trees_depleted);
+    println!("  Trees set on fire: {}", totals.trees_set_on_fire);
+    println!("  Trees extinguished: {}", totals.trees_extinguished);
+    println!("  Entities moved: {}", totals.entities_moved);
+    println!("  Total score gained: {}", totals.score_gained);
+
+    for (entity_id, changes) in &report.changed {
+        println!("{}", entity_id);
+
+        for (component_id, change) in changes {
+            let description = match change {
+                Change::Moved { from, to, distance } => format!(
+                    "moved {:.1} from ({:.1}, {:.1}, {:.1}) to ({:.1}, {:.1}, {:.1})",
+                    distance, from[0], from[1], from[2], to[0], to[1], to[2]
+                ),
+                Change::Resources { before, after, .. } => {
+                    format!("resources {} -> {}", before, after)
+                }
+                Change::Score { before, after, delta } => {
+                    format!("score {} -> {} ({:+})", before, after, delta)
+                }
+                Change::Fire { before, after } => format!("on fire {} -> {}", before, after),
+                Change::Action { before, after } => format!("action {} -> {}", before, after),
+            };
+
+            println!("  [{}] {}", component_id, description);
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    added: Vec<i64>,
+    removed: Vec<i64>,
+    changed: BTreeMap<i64, BTreeMap<ComponentId, Change>>,
+    totals: Totals,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Change {
+    Moved {
+        from: [f64; 3],
+        to: [f64; 3],
+        distance: f64,
+    },
+    Resources {
+        before: u32,
+        after: u32,
+        depleted: bool,
+    },
+    Score {
+        before: u32,
+        after: u32,
+        delta: i64,
+    },
+    Fire {
+        before: bool,
+        after: bool,
+    },
+    Action {
+        before: String,
+        after: String,
+    },
+}
+
+#[derive(Serialize, Default)]
+struct Totals {
+    trees_depleted: usize,
+    trees_set_on_fire: usize,
+    trees_extinguished: usize,
+    entities_moved: usize,
+    score_gained: i64,
+}
+
+impl Totals {
+    fn count(&mut self, changes: &BTreeMap<ComponentId, Change>) {
+        for change in changes.values() {
+            match change {
+                Change::Resources { depleted: true, .. } => self.trees_depleted += 1,
+                Change::Fire { after: true, .. } => self.trees_set_on_fire += 1,
+                Change::Fire { after: false, .. } => self.trees_extinguished += 1,
+                Change::Moved { .. } => self.entities_moved += 1,
+                Change::Score { delta, .. } => self.score_gained += delta,
+                _ => {}
+            }
+        }
+    }
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "snapshot-diff", about = "Reports what changed between two snapshots.")]
+struct Opt {
+    /// The earlier snapshot, e.g. snapshots/default.snapshot.
+    #[structopt(short = "b", long = "before")]
+    before: PathBuf,
+
+    /// The later snapshot.
+    #[structopt(short = "a", long = "after")]
+    after: PathBuf,
+
+    /// Print the report as JSON.
+    #[structopt(long = "json")]
+    json: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_ldn_demo::shared::GAMELOGIC_LAYER;
+    use spatialos_sdk::worker::entity_builder::EntityBuilder;
+
+    fn tree(x: f64, resources: u32, is_on_fire: bool) -> Entity {
+        let mut builder = EntityBuilder::new(x, 0.0, 0.0, GAMELOGIC_LAYER);
+        builder.add_component(
+            Tree {
+                resources_left: resources,
+                max_resources: 5,
+            },
+            GAMELOGIC_LAYER,
+        );
+        builder.add_component(Fire { is_on_fire }, GAMELOGIC_LAYER);
+        builder.build().unwrap()
+    }
+
+    fn hq(score: u32) -> Entity {
+        let mut builder = EntityBuilder::new(0.0, 0.0, 0.0, GAMELOGIC_LAYER);
+        builder.add_component(Headquarters { score, team: 0, lumberjack_radius: 15 }, GAMELOGIC_LAYER);
+        builder.build().unwrap()
+    }
+
+    fn snapshot(entities: Vec<(i64, Entity)>) -> BTreeMap<EntityId, Entity> {
+        entities
+            .into_iter()
+            .map(|(id, entity)| (EntityId::new(id), entity))
+            .collect()
+    }
+
+    #[test]
+    fn reports_added_and_removed_entities() {
+        let before = snapshot(vec![(1, tree(0.0, 5, false)), (2, tree(1.0, 5, false))]);
+        let after = snapshot(vec![(2, tree(1.0, 5, false)), (3, tree(2.0, 5, false))]);
+
+        let report = diff(&before, &after);
+
+        assert_eq!(report.added, vec![3]);
+        assert_eq!(report.removed, vec![1]);
+        assert!(report.changed.is_empty());
+    }
+
+    #[test]
+    fn reports_changed_components() {
+        let before = snapshot(vec![(1, tree(0.0, 5, false)), (2, hq(10))]);
+        let after = snapshot(vec![(1, tree(3.0, 0, true)), (2, hq(17))]);
+
+        let report = diff(&before, &after);
+        let tree_changes = &report.changed[&1];
+
+        match tree_changes[&Position::ID] {
+            Change::Moved { distance, .. } => assert!((distance - 3.0).abs() < 1e-9),
+            _ => panic!("Expected the tree to have moved"),
+        }
+
+        match tree_changes[&Tree::ID] {
+            Change::Resources { before, after, depleted } => {
+                assert_eq!((before, after, depleted), (5, 0, true))
+            }
+            _ => panic!("Expected the tree's resources to have changed"),
+        }
+
+        match tree_changes[&Fire::ID] {
+            Change::Fire { before, after } => assert_eq!((before, after), (false, true)),
+            _ => panic!("Expected the tree to have caught fire"),
+        }
+
+        match report.changed[&2][&Headquarters::ID] {
+            Change::Score { before, after, delta } => assert_eq!((before, after, delta), (10, 17, 7)),
+            _ => panic!("Expected the HQ's score to have changed"),
+        }
+    }
+
+    #[test]
+    fn ignores_unchanged_entities_and_tiny_moves() {
+        let before = snapshot(vec![(1, tree(0.0, 5, false)), (2, hq(3))]);
+        let after = snapshot(vec![(1, tree(MOVE_THRESHOLD / 2.0, 5, false)), (2, hq(3))]);
+
+        assert!(diff(&before, &after).changed.is_empty());
+    }
+
+    #[test]
+    fn totals_add_up_the_changes() {
+        let before = snapshot(vec![
+            (1, tree(0.0, 5, false)),
+            (2, tree(0.0, 2, true)),
+            (3, tree(0.0, 0, false)),
+            (4, hq(10)),
+            (5, hq(20)),
+        ]);
+        let after = snapshot(vec![
+            (1, tree(1.0, 0, true)),
+            (2, tree(0.0, 0, false)),
+            (3, tree(0.0, 0, false)),
+            (4, hq(15)),
+            (5, hq(18)),
+        ]);
+
+        let totals = diff(&before, &after).totals;
+
+        assert_eq!(totals.trees_depleted, 2);
+        assert_eq!(totals.trees_set_on_fire, 1);
+        assert_eq!(totals.trees_extinguished, 1);
+        assert_eq!(totals.entities_moved, 1);
+        assert_eq!(totals.score_gained, 3);
+    }
+}
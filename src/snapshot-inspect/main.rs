@@ -0,0 +1,222 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use rust_ldn_demo::shared::generated::demo::{Headquarters, Tree};
+use rust_ldn_demo::shared::generated::improbable::{Metadata, Position};
+use rust_ldn_demo::shared::snapshot::{component_names, components_of, read_snapshot};
+use spatialos_sdk::worker::entity::Entity;
+use spatialos_sdk::worker::EntityId;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let opt = Opt::from_args();
+    let entities = read_snapshot(&opt.snapshot_path)?;
+
+    let report = Report {
+        summary: summarize(&entities),
+        entities: match opt.component {
+            Some(ref name) => Some(list_entities(&entities, name)?),
+            None => None,
+        },
+    };
+
+    if opt.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
+
+fn summarize(entities: &BTreeMap<EntityId, Entity>) -> Summary {
+    let mut components = BTreeMap::new();
+    let mut bounds: Option<Bounds> = None;
+    let mut tree_resources = 0;
+    let mut hq_scores = BTreeMap::new();
+    let mut team_scores = BTreeMap::new();
+
+    for (entity_id, entity) in entities {
+        for (_, name) in components_of(entity) {
+            *components.entry(name).or_insert(0) += 1;
+        }
+
+        if let Some(position) = entity.get::<Position>() {
+            let coords = &position.coords;
+            let point = [coords.x, coords.y, coords.z];
+
+            bounds = Some(match bounds {
+                Some(mut b) => {
+                    for i in 0..3 {
+                        b.min[i] = b.min[i].min(point[i]);
+                        b.max[i] = b.max[i].max(point[i]);
+                    }
+                    b
+                }
+                None => Bounds {
+                    min: point,
+                    max: point,
+                },
+            });
+        }
+
+        if let Some(tree) = entity.get::<Tree>() {
+            tree_resources += u64::from(tree.resources_left);
+        }
+
+        if let Some(hq) = entity.get::<Headquarters>() {
+            hq_scores.insert(entity_id.id, hq.score);
+            *team_scores.entry(hq.team).or_insert(0) += u64::from(hq.score);
+        }
+    }
+
+    Summary {
+        entity_count: entities.len(),
+        components,
+        bounds,
+        tree_resources,
+        hq_scores,
+        team_scores,
+    }
+}
+
+fn list_entities(
+    entities: &BTreeMap<EntityId, Entity>,
+    component: &str,
+) -> Result<Vec<EntityListing>, String> {
+    let names = component_names();
+    if !names.iter().any(|name| name.eq_ignore_ascii_case(component)) {
+        return Err(format!(
+            "Unknown component \"{}\", expected one of {}",
+            component,
+            names.join(", ")
+        ));
+    }
+
+    let listings = entities
+        .iter()
+        .filter_map(|(entity_id, entity)| {
+            let components = components_of(entity)
+                .into_iter()
+                .map(|(_, name)| name)
+                .collect::<Vec<&'static str>>();
+
+            if !components
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(component))
+            {
+                return None;
+            }
+
+            Some(EntityListing {
+                entity_id: entity_id.id,
+                name: entity
+                    .get::<Metadata>()
+                    .map(|metadata| metadata.entity_type.clone()),
+                position: entity.get::<Position>().map(|position| {
+                    let coords = &position.coords;
+                    [coords.x, coords.y, coords.z]
+                }),
+                components,
+            })
+        })
+        .collect();
+
+    Ok(listings)
+}
+
+fn print_report(report: &Report) {
+    let summary = &report.summary;
+
+    println!("Entities: {}", summary.entity_count);
+
+    println!("Components:");
+    for (name, count) in &summary.components {
+        println!("  {:<14} {}", name, count);
+    }
+
+    match summary.bounds {
+        Some(ref b) => println!(
+            "Bounds: ({:.1}, {:.1}, {:.1}) to ({:.1}, {:.1}, {:.1})",
+            b.min[0], b.min[1], b.min[2], b.max[0], b.max[1], b.max[2]
+        ),
+        None => println!("Bounds: no positioned entities"),
+    }
+
+    println!("Tree resources: {}", summary.tree_resources);
+
+    println!("HQ scores:");
+    for (entity_id, score) in &summary.hq_scores {
+        println!("  {:<14} {}", entity_id, score);
+    }
+
+    println!("Team scores:");
+    for (team, score) in &summary.team_scores {
+        println!("  {:<14} {}", team, score);
+    }
+
+    if let Some(ref entities) = report.entities {
+        println!("Matching entities: {}", entities.len());
+        for entity in entities {
+            let position = match entity.position {
+                Some(p) => format!("({:.1}, {:.1}, {:.1})", p[0], p[1], p[2]),
+                None => "-".to_owned(),
+            };
+
+            println!(
+                "  {:<8} {:<20} {:<28} {}",
+                entity.entity_id,
+                entity.name.as_ref().map(String::as_str).unwrap_or("-"),
+                position,
+                entity.components.join(", ")
+            );
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    summary: Summary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entities: Option<Vec<EntityListing>>,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    entity_count: usize,
+    components: BTreeMap<&'static str, usize>,
+    bounds: Option<Bounds>,
+    tree_resources: u64,
+    hq_scores: BTreeMap<i64, u32>,
+    team_scores: BTreeMap<u32, u64>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+struct Bounds {
+    min: [f64; 3],
+    max: [f64; 3],
+}
+
+#[derive(Serialize)]
+struct EntityListing {
+    entity_id: i64,
+    name: Option<String>,
+    position: Option<[f64; 3]>,
+    components: Vec<&'static str>,
+}
+
+#[derive(StructOpt, Debug)]
+#[structopt(name = "snapshot-inspect", about = "Prints a summary of a snapshot's contents.")]
+struct Opt {
+    #[structopt(short = "p", long = "snapshot-path")]
+    snapshot_path: PathBuf,
+
+    /// List every entity that has this component, e.g. "tree" or "lumberjack".
+    #[structopt(short = "c", long = "component")]
+    component: Option<String>,
+
+    /// Print the report as JSON.
+    #[structopt(long = "json")]
+    json: bool,
+}